@@ -0,0 +1,193 @@
+//! Versioning and format-preserving updates of the config file.
+//!
+//! Old config files are upgraded in place when they are loaded, after a backup is made. When the
+//! config is saved, the changes are merged into the existing file so comments, formatting and keys
+//! that multigit doesn't know about are kept.
+
+use crate::Config;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// The version of the config file format written by this version of multigit.
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Upgrades a config document by one version. The migration at index `n` upgrades version `n`.
+type Migration = fn(&mut DocumentMut) -> Result<()>;
+
+/// The migrations, in order. Files without a `version` key are version 0.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [
+    // Version 1 only introduced the `version` key.
+    |_| Ok(()),
+];
+
+/// Returns the version of a config document.
+fn version(document: &DocumentMut) -> i64 {
+    document
+        .get("version")
+        .and_then(|version| version.as_integer())
+        .unwrap_or(0)
+}
+
+/// Upgrades the config file at `path` with contents `content` to `CONFIG_VERSION`.
+///
+/// With `save`, the upgraded file is written and the original is kept next to it as
+/// `config.toml.v<N>.bak`. Returns the upgraded contents, or `content` unchanged if it is
+/// current, newer or can't be parsed.
+pub(crate) fn migrate(path: &Path, content: &str, save: bool) -> Result<String> {
+    let Ok(mut document) = content.parse::<DocumentMut>() else {
+        return Ok(content.to_string());
+    };
+    let from = version(&document);
+    if from > CONFIG_VERSION {
+        log::warn!(
+            "{} is version {}, which is newer than this multigit supports ({}). It will not be saved.",
+            path.display(),
+            from,
+            CONFIG_VERSION
+        );
+        return Ok(content.to_string());
+    }
+    if from == CONFIG_VERSION {
+        return Ok(content.to_string());
+    }
+    for migration in &MIGRATIONS[from.max(0) as usize..] {
+        migration(&mut document)?;
+    }
+    document["version"] = toml_edit::value(CONFIG_VERSION);
+    if !save {
+        return Ok(document.to_string());
+    }
+
+    let backup = backup_path(path, from);
+    fs::write(&backup, content)
+        .with_context(|| format!("Failed to back up config to {}", backup.display()))?;
+    let upgraded = document.to_string();
+    fs::write(path, &upgraded)?;
+    log::info!(
+        "Upgraded {} from version {} to {}, the original is at {}",
+        path.display(),
+        from,
+        CONFIG_VERSION,
+        backup.display()
+    );
+    Ok(upgraded)
+}
+
+/// Returns where the version `version` original of a config file is backed up.
+fn backup_path(path: &Path, version: i64) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+/// Returns the contents of the config file after saving `config` over `existing`.
+///
+/// Values that changed are replaced and entries that were removed are deleted. Everything else in
+/// `existing`, including comments and unknown keys, is kept as it was.
+pub(crate) fn merge(existing: &str, config: &Config) -> Result<String> {
+    let written = toml::Value::try_from(config)?;
+    let Ok(mut document) = existing.parse::<DocumentMut>() else {
+        return Ok(toml::to_string(&written)?);
+    };
+    if version(&document) > CONFIG_VERSION {
+        return Err(anyhow::anyhow!(
+            "Not saving the config, its version {} is newer than this multigit supports ({})",
+            version(&document),
+            CONFIG_VERSION
+        ));
+    }
+    // The keys multigit understood in the existing file. Any other key is unknown and kept.
+    let known = toml::from_str::<Config>(existing)
+        .ok()
+        .and_then(|config| toml::Value::try_from(config).ok());
+    merge_table(
+        document.as_table_mut(),
+        known.as_ref().and_then(|known| known.as_table()),
+        written.as_table().context("The config is not a table")?,
+    );
+    Ok(document.to_string())
+}
+
+/// Merges `written` into `table`, removing the keys of `known` that are no longer written.
+fn merge_table(table: &mut dyn TableLike, known: Option<&toml::Table>, written: &toml::Table) {
+    for (key, value) in written {
+        let known = known.and_then(|known| known.get(key));
+        match (table.get_mut(key), value) {
+            (Some(item), toml::Value::Table(written)) if item.is_table_like() => {
+                let item = item.as_table_like_mut().unwrap();
+                merge_table(item, known.and_then(|known| known.as_table()), written);
+            }
+            (Some(item), value) => {
+                if to_toml(item).as_ref() != Some(value) {
+                    let mut new = to_item(value);
+                    if let (Some(old), Some(new)) = (item.as_value(), new.as_value_mut()) {
+                        *new.decor_mut() = old.decor().clone();
+                    }
+                    *item = new;
+                }
+            }
+            (None, value) => {
+                table.insert(key, to_item(value));
+            }
+        }
+    }
+    let removed: Vec<String> = table
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| {
+            !written.contains_key(key) && known.is_some_and(|known| known.contains_key(key))
+        })
+        .collect();
+    for key in removed {
+        table.remove(&key);
+    }
+}
+
+/// Converts a value of the config document to a `toml::Value`, ignoring its formatting.
+fn to_toml(item: &Item) -> Option<toml::Value> {
+    let mut value = item.as_value()?.clone();
+    value.decor_mut().clear();
+    let table: toml::Table = toml::from_str(&format!("value = {}", value)).ok()?;
+    table.get("value").cloned()
+}
+
+/// Converts a `toml::Value` to an item of the config document.
+///
+/// Tables become standard tables, and are left implicit when they only contain other tables.
+fn to_item(value: &toml::Value) -> Item {
+    match value {
+        toml::Value::Table(table) => {
+            let mut item = toml_edit::Table::new();
+            for (key, value) in table {
+                item.insert(key, to_item(value));
+            }
+            item.set_implicit(table.values().all(|value| value.is_table()));
+            Item::Table(item)
+        }
+        value => Item::Value(to_value(value)),
+    }
+}
+
+/// Converts a `toml::Value` to an inline value of the config document.
+fn to_value(value: &toml::Value) -> toml_edit::Value {
+    match value {
+        toml::Value::String(string) => string.into(),
+        toml::Value::Integer(integer) => (*integer).into(),
+        toml::Value::Float(float) => (*float).into(),
+        toml::Value::Boolean(boolean) => (*boolean).into(),
+        toml::Value::Datetime(datetime) => datetime
+            .to_string()
+            .parse()
+            .unwrap_or_else(|_| datetime.to_string().into()),
+        toml::Value::Array(array) => array.iter().map(to_value).collect(),
+        toml::Value::Table(table) => {
+            let mut inline = toml_edit::InlineTable::new();
+            for (key, value) in table {
+                inline.insert(key, to_value(value));
+            }
+            toml_edit::Value::InlineTable(inline)
+        }
+    }
+}
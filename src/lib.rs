@@ -2,42 +2,197 @@
 //!
 //! This library provides functionalities to register, unregister, list, and perform Git operations on multiple repositories.
 //! It supports filtering repositories based on their state and provides utilities to execute commands across repositories.
+//!
+//! The `multigit` command line tool is built on this crate, and other tools can embed it too:
+//! load a `Config`, create a `Multigit`, select repositories and then inspect them with
+//! `Multigit::summaries` or run an operation in them with `Multigit::run_repositories`. Both
+//! return typed results and print nothing; retries are reported with `log::warn!`. Only setting
+//! `Multigit::pick` makes selecting repositories interactive.
+//!
+//! The methods that implement the tool's commands, such as `Multigit::status`, `doctor`,
+//! `relocate`, `exec`, `diff`, `apply` and the git commands, print their output to the terminal
+//! instead.
+//!
+//! ```no_run
+//! use multigit::{Config, Filter, InputArg, Multigit, DEFAULT_CONFIG};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut config =
+//!     Config::load(InputArg::Path(DEFAULT_CONFIG.into()))?.select_profile(None, false)?;
+//! // Optionally apply the `.multigit.toml` project configs, as the command line tool does.
+//! config.load_layers(&std::env::current_dir()?)?;
+//! let multigit = Multigit::new(config, None)?;
+//!
+//! for summary in multigit.summaries(Some(&vec![Filter::Dirty]))? {
+//!     println!("{} is dirty on {:?}", summary.name, summary.branch);
+//! }
+//!
+//! let repositories = multigit.all_repositories(None)?;
+//! let report = multigit.run_repositories(&repositories, |repository| {
+//!     repository.has_remote().map(|_| ())
+//! });
+//! for error in &report.errors {
+//!     eprintln!("{}: {}", error.path.display(), error.error);
+//! }
+//! # Ok(())
+//! # }
+//! ```
 
 use anyhow::{anyhow, Context, Result};
 use colored_markup::{println_markup, StyleSheet};
 use fern::colors::{Color, ColoredLevelConfig};
-use inquire::Confirm;
+use fs2::FileExt;
+use inquire::{Confirm, MultiSelect};
 use path_absolutize::Absolutize;
-use patharg::InputArg;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::fs;
 use std::io;
-use std::io::Read;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
 use std::path::{Display, Path, PathBuf};
-use std::process::Command;
-use std::time::SystemTime;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tabled::{Table, Tabled};
+use wait_timeout::ChildExt;
 use walkdir::WalkDir;
 
+mod config_file;
+mod plugin;
+mod shell;
+mod tui;
+
+pub use config_file::CONFIG_VERSION;
+pub use patharg::InputArg;
+pub use plugin::{find_plugin, PLUGIN_PREFIX};
+pub use shell::{shell_init, InitShell};
+
 /// Represents an entry for a single Git repository.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RepositoryEntry {
     /// The path to the repository.
     pub path: PathBuf,
+
+    /// A short name to select the repository by on the command line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+
+    /// What the repository is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The person or team responsible for the repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// The branch work is merged into, e.g. `main`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+
+    /// Any other metadata, set with `multigit repo set <repo> key=value`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+
+    /// The remote URL recorded at registration, used to find the repository again if it moves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+
+    /// The config entries the repository was found through, e.g. `directories."/src"`. Not saved.
+    #[serde(skip)]
+    pub sources: Vec<String>,
 }
 
 impl RepositoryEntry {
-    fn current_branch(&self) -> Result<String> {
+    /// Creates an entry for the repository at `path`.
+    pub fn new(path: PathBuf) -> Self {
+        RepositoryEntry {
+            path,
+            alias: None,
+            description: None,
+            owner: None,
+            default_branch: None,
+            metadata: BTreeMap::new(),
+            remote_url: None,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Returns a metadata value, either one of the `METADATA_FIELDS` or a custom key.
+    pub fn metadata_value(&self, key: &str) -> Option<&String> {
+        match key {
+            "alias" => self.alias.as_ref(),
+            "description" => self.description.as_ref(),
+            "owner" => self.owner.as_ref(),
+            "default_branch" => self.default_branch.as_ref(),
+            _ => self.metadata.get(key),
+        }
+    }
+
+    /// Sets a metadata value, or removes it if `value` is `None`.
+    pub fn set_metadata_value(&mut self, key: &str, value: Option<String>) {
+        match key {
+            "alias" => self.alias = value,
+            "description" => self.description = value,
+            "owner" => self.owner = value,
+            "default_branch" => self.default_branch = value,
+            _ => match value {
+                Some(value) => {
+                    self.metadata.insert(key.to_string(), value);
+                }
+                None => {
+                    self.metadata.remove(key);
+                }
+            },
+        }
+    }
+
+    /// Returns whether the repository matches a filter.
+    pub fn matches(&self, filter: &Filter) -> bool {
+        match filter {
+            Filter::Dirty => self.is_dirty(),
+            Filter::Staged => self.has_staged_changes().unwrap_or(false),
+            Filter::Tracking => self.has_tracking_branch().unwrap_or(false),
+            Filter::Ahead => self.ahead_remote().ok().flatten() == Some(true),
+            Filter::Behind => self.behind_remote().ok().flatten() == Some(true),
+            Filter::HasRemote => self.has_remote().unwrap_or(false),
+            Filter::Metadata { key, value } => self.metadata_value(key) == Some(value),
+        }
+    }
+
+    /// Returns the repository's directory name.
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.path.display().to_string())
+    }
+
+    /// Returns the name of the current branch, including one without any commits yet.
+    pub fn current_branch(&self) -> Result<String> {
         let repo = git2::Repository::open(&self.path)?;
-        let head = repo.head()?;
-        let branch = head.shorthand().unwrap();
+        let head = match repo.head() {
+            Ok(head) => head,
+            // An unborn branch has no reference yet, but `HEAD` still names it.
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => {
+                let head = repo.find_reference("HEAD")?;
+                let target = head
+                    .symbolic_target()
+                    .context("Branch name is not valid UTF-8")?;
+                return Ok(target.trim_start_matches("refs/heads/").to_string());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let branch = head.shorthand().context("Branch name is not valid UTF-8")?;
         Ok(branch.to_string())
     }
 
-    fn has_tracking_branch(&self) -> Result<bool, git2::Error> {
+    /// Returns whether the current branch has an upstream branch.
+    pub fn has_tracking_branch(&self) -> Result<bool, git2::Error> {
         let repo = git2::Repository::open(&self.path)?;
         let has_upstream = repo
             .head()
@@ -50,26 +205,30 @@ impl RepositoryEntry {
         Ok(has_upstream)
     }
 
-    fn behind_remote(&self) -> Result<Option<bool>> {
+    /// Returns whether the current branch is missing commits from its upstream, or `None` if it
+    /// has no upstream.
+    pub fn behind_remote(&self) -> Result<Option<bool>> {
         let repo = git2::Repository::open(&self.path)?;
         let head = repo.head()?;
-        let branch = head.shorthand().unwrap();
+        let branch = head.shorthand().context("Branch name is not valid UTF-8")?;
         let branch = repo.find_branch(branch, git2::BranchType::Local)?;
         if branch.upstream().is_err() {
             return Ok(None);
         }
         let upstream = branch.upstream()?;
         let (_, behind) = repo.graph_ahead_behind(
-            branch.get().target().unwrap(),
-            upstream.get().target().unwrap(),
+            branch.get().target().context("Branch has no target")?,
+            upstream.get().target().context("Upstream has no target")?,
         )?;
         Ok(Some(behind > 0))
     }
 
-    fn ahead_remote(&self) -> Result<Option<bool>> {
+    /// Returns whether the current branch has commits that its upstream doesn't, or `None` if it
+    /// has no upstream.
+    pub fn ahead_remote(&self) -> Result<Option<bool>> {
         let repo = git2::Repository::open(&self.path)?;
         let head = repo.head()?;
-        let branch = head.shorthand().unwrap();
+        let branch = head.shorthand().context("Branch name is not valid UTF-8")?;
         let branch = repo.find_branch(branch, git2::BranchType::Local)?;
         // if no upstream is set, return None
         if branch.upstream().is_err() {
@@ -77,13 +236,121 @@ impl RepositoryEntry {
         }
         let upstream = branch.upstream()?;
         let (ahead, _) = repo.graph_ahead_behind(
-            branch.get().target().unwrap(),
-            upstream.get().target().unwrap(),
+            branch.get().target().context("Branch has no target")?,
+            upstream.get().target().context("Upstream has no target")?,
         )?;
         Ok(Some(ahead > 0))
     }
 
-    fn has_stashes(&self) -> Result<bool> {
+    /// Returns the URL of the remote the current branch tracks, falling back to `origin`.
+    pub fn tracking_remote_url(&self) -> Result<String> {
+        let repo = git2::Repository::open(&self.path)?;
+        let remote = tracking_remote(&repo)?;
+        let url = remote.url().context("Remote URL is not valid UTF-8")?;
+        Ok(url.to_string())
+    }
+
+    /// Returns the shorthand name of the current branch's upstream, e.g. `origin/main`.
+    pub fn upstream(&self) -> Result<String> {
+        let repo = git2::Repository::open(&self.path)?;
+        let branch = repo.find_branch(&self.current_branch()?, git2::BranchType::Local)?;
+        let upstream = branch
+            .upstream()
+            .context("Current branch has no upstream")?;
+        let name = upstream
+            .name()?
+            .context("Upstream name is not valid UTF-8")?;
+        Ok(name.to_string())
+    }
+
+    /// Expands `{path}`, `{name}`, `{repo}`, `{branch}`, `{remote_url}` and `{upstream}` in
+    /// `template` for this repository, as well as metadata such as `{owner}` and `{meta.KEY}`
+    /// for custom keys. Other braces are left untouched, including git's `@{...}` revision syntax,
+    /// and `{{` is a literal `{`.
+    pub fn expand_template(&self, template: &str) -> Result<String> {
+        self.expand_placeholders(template, false)
+    }
+
+    /// Like `expand_template`, but quotes each value for use in a `sh -c` command line.
+    fn expand_shell_template(&self, template: &str) -> Result<String> {
+        self.expand_placeholders(template, true)
+    }
+
+    /// Expands the placeholders of `template` in a single pass, so values are never expanded
+    /// again themselves.
+    fn expand_placeholders(&self, template: &str, shell_quote: bool) -> Result<String> {
+        let mut expanded = String::with_capacity(template.len());
+        let mut position = 0;
+        while let Some(offset) = template[position..].find('{') {
+            let start = position + offset;
+            expanded.push_str(&template[position..start]);
+            let rest = &template[start..];
+            if rest.starts_with("{{") {
+                expanded.push('{');
+                position = start + 2;
+                continue;
+            }
+            // `@{upstream}`, `@{1}` and the like are git revisions, not placeholders.
+            let value = match rest.find('}') {
+                Some(end) if !template[..start].ends_with('@') => self
+                    .placeholder_value(&rest[1..end])?
+                    .map(|value| (value, end)),
+                _ => None,
+            };
+            match value {
+                Some((value, end)) => {
+                    if shell_quote {
+                        expanded.push_str(&shell_words::quote(&value));
+                    } else {
+                        expanded.push_str(&value);
+                    }
+                    position = start + end + 1;
+                }
+                None => {
+                    expanded.push('{');
+                    position = start + 1;
+                }
+            }
+        }
+        expanded.push_str(&template[position..]);
+        Ok(expanded)
+    }
+
+    /// Returns the value of the placeholder `{key}`, or `None` if `key` isn't a placeholder.
+    fn placeholder_value(&self, key: &str) -> Result<Option<String>> {
+        if let Some(key) = key.strip_prefix("meta.") {
+            let value = self
+                .metadata
+                .get(key)
+                .with_context(|| format!("Repository has no `{}` set", key))?;
+            return Ok(Some(value.clone()));
+        }
+        if !TEMPLATE_PLACEHOLDERS.contains(&key) {
+            return Ok(None);
+        }
+        let value = match key {
+            "path" => self.path.absolutize()?.display().to_string(),
+            "name" | "repo" => self.name(),
+            "branch" => self.current_branch()?,
+            "remote_url" => self.tracking_remote_url()?,
+            "upstream" => self.upstream()?,
+            field => self
+                .metadata_value(field)
+                .cloned()
+                .with_context(|| format!("Repository has no `{}` set", field))?,
+        };
+        Ok(Some(value))
+    }
+
+    /// Returns whether the repository has at least one remote.
+    pub fn has_remote(&self) -> Result<bool> {
+        let repo = git2::Repository::open(&self.path)?;
+        let has_remote = !repo.remotes()?.is_empty();
+        Ok(has_remote)
+    }
+
+    /// Returns whether the repository has any stashed changes.
+    pub fn has_stashes(&self) -> Result<bool> {
         let mut repo = git2::Repository::open(&self.path)?;
         let mut has_stashes = false;
         repo.stash_foreach(|_, _, _| {
@@ -94,8 +361,25 @@ impl RepositoryEntry {
     }
 }
 
+/// Placeholders expanded by `RepositoryEntry::expand_template`.
+pub const TEMPLATE_PLACEHOLDERS: &[&str] = &[
+    "path",
+    "name",
+    "repo",
+    "branch",
+    "remote_url",
+    "upstream",
+    "alias",
+    "description",
+    "owner",
+    "default_branch",
+];
+
+/// The metadata stored in their own fields of `RepositoryEntry`. Other keys go in `metadata`.
+pub const METADATA_FIELDS: &[&str] = &["alias", "description", "owner", "default_branch"];
+
 /// Represents an entry for a directory containing Git repositories.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DirectoryEntry {
     /// The path to the directory.
     pub path: PathBuf,
@@ -136,16 +420,35 @@ impl RepositoryEntry {
         anyhow::Ok(state)
     }
 
-    #[allow(dead_code)]
+    /// Returns whether the index has changes that `git commit` would commit.
+    pub fn has_staged_changes(&self) -> Result<bool> {
+        let repo = git2::Repository::open(&self.path)?;
+        let mut status_options = git2::StatusOptions::new();
+        status_options.include_untracked(false);
+        let statuses = repo.statuses(Some(&mut status_options))?;
+        let staged = git2::Status::INDEX_NEW
+            | git2::Status::INDEX_MODIFIED
+            | git2::Status::INDEX_DELETED
+            | git2::Status::INDEX_RENAMED
+            | git2::Status::INDEX_TYPECHANGE;
+        Ok(statuses
+            .iter()
+            .any(|status| status.status().intersects(staged)))
+    }
+
     fn is_dirty(&self) -> bool {
-        let state = self.state().unwrap();
-        state.entries.contains(&EntryState::Dirty)
+        self.state()
+            .is_ok_and(|state| state.entries.contains(&EntryState::Dirty))
     }
 }
 
 /// Configuration data for the application, including registered repositories and directories.
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Config {
+    /// The version of the config file format. Older files are upgraded when they are loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+
     /// A map of repository names to their entries.
     #[serde(default = "HashMap::new")]
     pub repositories: HashMap<String, RepositoryEntry>,
@@ -153,25 +456,185 @@ pub struct Config {
     /// A map of directory names to their entries.
     #[serde(default = "HashMap::new")]
     pub directories: HashMap<String, DirectoryEntry>,
+
+    /// Filters applied to a command when it is run without `--filter` or `--all`, keyed by command name.
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    pub default_filters: HashMap<String, Vec<Filter>>,
+
+    /// Per-command settings such as timeouts and retries, keyed by command name.
+    #[serde(default = "HashMap::new", skip_serializing_if = "HashMap::is_empty")]
+    pub commands: HashMap<String, CommandSettings>,
+
+    /// Whether commands stop at the first repository that fails, unless `--keep-going` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fail_fast: Option<bool>,
+
+    /// Settings for `multigit daemon`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daemon: Option<DaemonSettings>,
+
+    /// How newly registered paths are written to the config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_style: Option<PathStyle>,
+
+    /// Repositories at or under these paths are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<PathBuf>,
+
+    /// Command aliases, e.g. `wip = "commit -m WIP"`. A definition starting with `!` is a shell
+    /// command run in each repository.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+
+    /// The profile used when neither `--profile` nor `MULTIGIT_PROFILE` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Named sets of repositories, directories and settings, configured under
+    /// `[profiles.<name>]`. Settings a profile doesn't configure are taken from the top level.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Config>,
+
+    /// If this is a profile, its name and the config it was selected from, which is what gets
+    /// saved.
+    #[serde(skip)]
+    parent: Option<(String, Box<Config>)>,
+
+    /// Project configs found in the working directory and its parents, nearest first. Their
+    /// entries are added to this config's, and their settings take precedence over it.
+    #[serde(skip)]
+    pub layers: Vec<Config>,
+
+    /// Project configs found in registered directories. Their excludes and default filters apply
+    /// to the repositories under the directory.
+    #[serde(skip)]
+    pub directory_layers: Vec<Config>,
+
+    /// The file the configuration was loaded from, and is saved back to.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    /// The paths as written in the config file, keyed by the absolute paths they resolve to, so
+    /// they are saved back unchanged.
+    #[serde(skip)]
+    written_paths: HashMap<PathBuf, String>,
+}
+
+/// How paths are written to the config file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathStyle {
+    /// Absolute paths.
+    #[default]
+    Absolute,
+    /// Paths under the home directory start with `~`.
+    Home,
+    /// Paths are relative to the directory containing the config file.
+    Relative,
+}
+
+/// Settings for the background fetch daemon, configured under `[daemon]`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DaemonSettings {
+    /// How long to wait between fetches, e.g. `"15m"`.
+    pub interval: Option<String>,
+}
+
+/// Settings for a single command, configured under `[commands.<name>]`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CommandSettings {
+    /// How long the command may run in each repository, e.g. `"30s"` or `"2m"`.
+    pub timeout: Option<String>,
+
+    /// How many times to retry the command in a repository after it times out or fails in a way
+    /// a retry may fix.
+    pub retries: Option<u32>,
+}
+
+/// Controls how long a command may run in a repository and how often it is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Kill the command if it runs longer than this.
+    pub timeout: Option<Duration>,
+
+    /// How many times to retry after a transient failure.
+    pub retries: u32,
+
+    /// The delay before the first retry, which doubles with each further retry.
+    pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: None,
+            retries: 0,
+            initial_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retry number `attempt`, doubling from `initial_delay`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_delay * (1 << attempt.min(6))
+    }
+
+    /// Runs `operation`, retrying it with backoff until it succeeds or the retries are used up.
+    ///
+    /// Only failures that a retry may fix are retried: timeouts, fatal git errors such as an
+    /// unreachable remote, and libgit2 network errors. Others, such as a rejected push, are
+    /// returned at once.
+    pub fn run<T, F>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation() {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    let delay = self.backoff(attempt);
+                    log::warn!("{}, retrying in {}", e, humantime::format_duration(delay));
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 }
 
+/// The user's config file, used when no other is given.
+pub const DEFAULT_CONFIG: &str = "~/.config/multigit/config.toml";
+
+/// The name of a project config file, which is merged over the user's config.
+pub const PROJECT_CONFIG: &str = ".multigit.toml";
+
+/// The name of the profile made up of the top-level repositories and directories.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The daemon's lock file in the state directory, named per profile.
+pub const DAEMON_LOCK_FILE_NAME: &str = "daemon.lock";
+
+/// The log of a daemon started in the background, named per profile.
+pub const DAEMON_LOG_FILE_NAME: &str = "daemon.log";
+
 impl Config {
     /// Loads the configuration from the default config file.
+    ///
+    /// A config written by an older version is upgraded, and the upgraded file saved.
     pub fn load(path: InputArg) -> Result<Self> {
-        log::debug!("{:?}", path);
-        // if file at path is missing, return default config
-        if let InputArg::Path(path) = &path {
-            let expanded_path = shellexpand::tilde(path.to_str().unwrap());
-            let path = PathBuf::from(expanded_path.to_string());
-            if !path.exists() {
-                log::info!(
-                    "Config file not found at '{:?}'. Using default configuration.",
-                    path
-                );
-                return Ok(Config::default());
-            }
-        }
+        Self::load_migrating(path, true)
+    }
 
+    /// Loads the configuration like `load`, but upgrades an older config in memory only, leaving
+    /// the file as it is. For lookups such as shell completion, which shouldn't write.
+    pub fn load_read_only(path: InputArg) -> Result<Self> {
+        Self::load_migrating(path, false)
+    }
+
+    fn load_migrating(path: InputArg, save_migration: bool) -> Result<Self> {
+        log::debug!("{:?}", path);
         let content = match path {
             InputArg::Stdin => {
                 let mut buffer = String::new();
@@ -181,56 +644,431 @@ impl Config {
             InputArg::Path(path) => {
                 let expanded_path = shellexpand::tilde(path.to_str().unwrap());
                 let config_path = PathBuf::from(expanded_path.to_string());
-                fs::read_to_string(config_path)
-                    .map_err(|e| anyhow!("Failed to read config file: {}", e))?
+                // if file at path is missing, return default config
+                if !config_path.exists() {
+                    log::info!(
+                        "Config file not found at '{:?}'. Using default configuration.",
+                        config_path
+                    );
+                    return Ok(Config {
+                        path: Some(config_path),
+                        ..Config::default()
+                    });
+                }
+                let content = fs::read_to_string(&config_path)
+                    .map_err(|e| anyhow!("Failed to read config file: {}", e))?;
+                let content = config_file::migrate(&config_path, &content, save_migration)?;
+                let mut config = Self::parse(&content);
+                config.path = Some(config_path);
+                config.resolve_paths()?;
+                return Ok(config);
             }
         };
+        let mut config = Self::parse(&content);
+        config.resolve_paths()?;
+        Ok(config)
+    }
+
+    /// Loads the project configs that apply when working in `directory`.
+    ///
+    /// A `.multigit.toml` in `directory` or any of its parents is added to `layers`, and one in
+    /// a registered directory to `directory_layers`. Each file is only loaded once.
+    pub fn load_layers(&mut self, directory: &Path) -> Result<()> {
+        let mut loaded = HashSet::new();
+        if let Some(path) = self.path.as_ref().and_then(|path| path.canonicalize().ok()) {
+            loaded.insert(path);
+        }
+        for ancestor in directory.absolutize()?.ancestors() {
+            if let Some(layer) = Self::load_layer(&ancestor.join(PROJECT_CONFIG), &mut loaded)? {
+                self.layers.push(layer);
+            }
+        }
+        let directories: Vec<PathBuf> = self
+            .directories
+            .values()
+            .chain(
+                self.layers
+                    .iter()
+                    .flat_map(|layer| layer.directories.values()),
+            )
+            .map(|entry| entry.path.clone())
+            .collect();
+        for directory in directories {
+            if let Some(layer) = Self::load_layer(&directory.join(PROJECT_CONFIG), &mut loaded)? {
+                self.directory_layers.push(layer);
+            }
+        }
+        Ok(())
+    }
 
-        toml::from_str(&content)
-            .map_err(|e| anyhow!("Failed to parse config: {}", e))
-            .or_else(|_| Ok(Config::default()))
+    /// Loads a project config, unless it doesn't exist or was already loaded.
+    fn load_layer(path: &Path, loaded: &mut HashSet<PathBuf>) -> Result<Option<Config>> {
+        if !path.is_file() || !loaded.insert(path.canonicalize()?) {
+            return Ok(None);
+        }
+        log::debug!("Loading project config {}", path.display());
+        // Project configs are often checked in, so unlike the user's config they aren't migrated.
+        let content = fs::read_to_string(path)?;
+        let mut config = Self::parse(&content);
+        config.path = Some(path.to_path_buf());
+        config.resolve_paths()?;
+        Ok(Some(config))
     }
 
-    /// Saves the current configuration to the default config file.
-    pub fn save(&self) -> Result<()> {
-        let config_path = "~/.config/multigit/config.toml";
-        // if file doesn't exist, create it and intermediate paths
-        if let Ok(path) = shellexpand::tilde(config_path)
-            .to_string()
-            .parse::<PathBuf>()
-        {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
+    /// The directory relative paths in the config file are resolved against.
+    fn base_directory(&self) -> Result<PathBuf> {
+        match self.path.as_ref().and_then(|path| path.parent()) {
+            Some(parent) => Ok(parent.absolutize()?.to_path_buf()),
+            None => Ok(env::current_dir()?),
+        }
+    }
+
+    /// Makes every repository and directory path absolute.
+    ///
+    /// Paths may start with `~`, contain `$VAR` or `${VAR}`, or be relative to the config file's
+    /// directory. The written forms are remembered so `save` writes them back unchanged.
+    fn resolve_paths(&mut self) -> Result<()> {
+        let base = self.base_directory()?;
+        let paths = self
+            .repositories
+            .values_mut()
+            .map(|entry| &mut entry.path)
+            .chain(self.directories.values_mut().map(|entry| &mut entry.path))
+            .chain(self.exclude.iter_mut());
+        for path in paths {
+            let written = path.to_string_lossy().to_string();
+            let resolved = resolve_config_path(&written, &base);
+            self.written_paths.insert(resolved.clone(), written);
+            *path = resolved;
+        }
+        for profile in self.profiles.values_mut() {
+            profile.path = self.path.clone();
+            profile.resolve_paths()?;
+        }
+        Ok(())
+    }
+
+    /// Selects a profile, returning its configuration.
+    ///
+    /// Without a name, the config's `profile` is used. The default profile is the config itself.
+    /// An unknown profile is an error unless `create` is set, in which case it is created empty.
+    /// A configured `profile` that no longer exists falls back to the default profile instead, so
+    /// `multigit profile use` can still fix it.
+    pub fn select_profile(mut self, name: Option<&str>, create: bool) -> Result<Config> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => match &self.profile {
+                Some(configured) if !self.profiles.contains_key(configured) => {
+                    if configured != DEFAULT_PROFILE {
+                        log::warn!(
+                            "The configured profile `{}` doesn't exist, using the default profile",
+                            configured
+                        );
+                    }
+                    return Ok(self);
+                }
+                Some(configured) => configured.clone(),
+                None => return Ok(self),
+            },
+        };
+        if name == DEFAULT_PROFILE {
+            return Ok(self);
+        }
+        let mut profile = match self.profiles.remove(&name) {
+            Some(profile) => profile,
+            None if create => {
+                log::info!("Creating profile `{}`", name);
+                Config {
+                    path: self.path.clone(),
+                    ..Config::default()
+                }
+            }
+            None => {
+                return Err(anyhow!(
+                    "No profile named `{}`. Run `multigit profile list` to see the profiles.",
+                    name
+                ))
             }
+        };
+        profile.parent = Some((name, Box::new(self)));
+        Ok(profile)
+    }
+
+    /// Returns the name of the selected profile.
+    pub fn profile_name(&self) -> &str {
+        match &self.parent {
+            Some((name, _)) => name,
+            None => DEFAULT_PROFILE,
+        }
+    }
+
+    /// Returns the names of all profiles, starting with the default profile.
+    pub fn profile_names(&self) -> Vec<String> {
+        let root = match &self.parent {
+            Some((_, parent)) => parent,
+            None => self,
+        };
+        let mut names = vec![DEFAULT_PROFILE.to_string()];
+        names.extend(root.profiles.keys().cloned());
+        if let Some((name, _)) = &self.parent {
+            names.push(name.clone());
+            names[1..].sort();
+        }
+        names
+    }
+
+    /// Sets the profile used when none is given on the command line, and saves the config.
+    pub fn set_default_profile(&mut self, name: &str) -> Result<()> {
+        if !self.profile_names().iter().any(|profile| profile == name) {
+            return Err(anyhow!(
+                "No profile named `{}`. Run `multigit profile list` to see the profiles.",
+                name
+            ));
+        }
+        let profile = if name == DEFAULT_PROFILE {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        match &mut self.parent {
+            Some((_, parent)) => parent.profile = profile,
+            None => self.profile = profile,
+        }
+        self.save()
+    }
+
+    /// Returns how an absolute path is written to the config file.
+    ///
+    /// Paths that were loaded from the file keep their written form. Other paths are written in
+    /// the configured `path_style`.
+    fn written_path(&self, path: &Path) -> Result<String> {
+        if let Some(written) = self.written_paths.get(path) {
+            return Ok(written.clone());
         }
+        let path_style = self.path_style.or_else(|| {
+            self.parent
+                .as_ref()
+                .and_then(|(_, parent)| parent.path_style)
+        });
+        self.written_path_in_style(path, path_style.unwrap_or_default())
+    }
+
+    /// Returns how `new` is written when it replaces `old`, in the form `old` was written in:
+    /// starting with `~`, relative to the config file, or absolute.
+    fn relocated_written_path(&self, old: &Path, new: &Path) -> Result<String> {
+        let path_style = match self.written_paths.get(old) {
+            Some(written) if written.starts_with('~') => PathStyle::Home,
+            // Paths using environment variables can't be rewritten reliably.
+            Some(written) if written.contains('$') => return self.written_path(new),
+            Some(written) if Path::new(written).is_relative() => PathStyle::Relative,
+            Some(_) => PathStyle::Absolute,
+            None => return self.written_path(new),
+        };
+        self.written_path_in_style(new, path_style)
+    }
+
+    /// Writes an absolute path in the given style.
+    fn written_path_in_style(&self, path: &Path, path_style: PathStyle) -> Result<String> {
+        let path = match path_style {
+            PathStyle::Absolute => path.to_path_buf(),
+            PathStyle::Home => {
+                let home = PathBuf::from(shellexpand::tilde("~").to_string());
+                match path.strip_prefix(&home) {
+                    Ok(rest) => Path::new("~").join(rest),
+                    Err(_) => path.to_path_buf(),
+                }
+            }
+            PathStyle::Relative => relative_path(path, &self.base_directory()?),
+        };
+        path.to_str()
+            .map(|path| path.to_string())
+            .context("Failed to convert path to string")
+    }
+
+    /// Parses a configuration, falling back to the default configuration if it is invalid.
+    fn parse(content: &str) -> Self {
+        toml::from_str(content)
+            .map_err(|e| log::warn!("Failed to parse config: {}", e))
+            .unwrap_or_default()
+    }
 
-        let config_path = shellexpand::tilde(config_path);
-        let config_path = config_path.to_string();
-        let config_content = toml::to_string(&self)?;
+    /// Saves the configuration to the file it was loaded from, or to the default config file.
+    pub fn save(&self) -> Result<()> {
+        let config_path = match &self.path {
+            Some(path) => path.clone(),
+            None => PathBuf::from(shellexpand::tilde("~/.config/multigit/config.toml").to_string()),
+        };
+        // if file doesn't exist, create it and intermediate paths
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Merge into the existing file, keeping its comments and any keys multigit doesn't know.
+        let written = self.written_file()?;
+        let config_content = match fs::read_to_string(&config_path) {
+            Ok(existing) => config_file::merge(&existing, &written)?,
+            Err(_) => toml::to_string(&written)?,
+        };
         std::fs::write(config_path, config_content)?;
         anyhow::Ok(())
     }
 
+    /// Returns the whole configuration as it is written to the config file.
+    ///
+    /// For a profile, this is the config it was selected from, with the profile in place.
+    pub fn written_file(&self) -> Result<Config> {
+        match &self.parent {
+            Some((name, parent)) => {
+                let mut written = parent.written_file()?;
+                written.profiles.insert(name.clone(), self.written()?);
+                Ok(written)
+            }
+            None => {
+                let mut written = self.written()?;
+                written.version = Some(CONFIG_VERSION);
+                Ok(written)
+            }
+        }
+    }
+
+    /// Returns this configuration with its paths as they are written to the config file.
+    fn written(&self) -> Result<Config> {
+        let mut written = self.clone();
+        written.parent = None;
+        for entry in written.repositories.values_mut() {
+            entry.path = PathBuf::from(self.written_path(&entry.path)?);
+        }
+        for entry in written.directories.values_mut() {
+            entry.path = PathBuf::from(self.written_path(&entry.path)?);
+        }
+        for path in written.exclude.iter_mut() {
+            *path = PathBuf::from(self.written_path(path)?);
+        }
+        for (name, profile) in &self.profiles {
+            written.profiles.insert(name.clone(), profile.written()?);
+        }
+        Ok(written)
+    }
+
+    /// Returns the default filters for a command run on the repository at `path`.
+    ///
+    /// Filters configured in `default_filters` replace the built-in defaults. Those of the
+    /// innermost registered directory containing the repository take precedence, then those of
+    /// the project configs, then this config's.
+    pub fn default_filters_for(&self, command: &str, path: &Path) -> Vec<Filter> {
+        let mut directory_layers: Vec<&Config> = self
+            .directory_layers
+            .iter()
+            .filter(|layer| layer.contains(path))
+            .collect();
+        directory_layers.sort_by_key(|layer| {
+            std::cmp::Reverse(layer.path.as_ref().map(|path| path.components().count()))
+        });
+        let configured = directory_layers
+            .into_iter()
+            .chain(&self.layers)
+            .chain([self])
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .find_map(|config| config.default_filters.get(command));
+        if let Some(filters) = configured {
+            return filters.clone();
+        }
+        match command {
+            "commit" => vec![Filter::Staged],
+            "push" => vec![Filter::Ahead],
+            "pull" => vec![Filter::Tracking],
+            "fetch" => vec![Filter::HasRemote],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the settings for a command, preferring those of the nearest project config.
+    pub fn command_settings(&self, command: &str) -> Option<&CommandSettings> {
+        self.layers
+            .iter()
+            .chain([self])
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .find_map(|config| config.commands.get(command))
+    }
+
+    /// Returns the definition of a command alias, preferring the profile's.
+    pub fn alias(&self, name: &str) -> Option<&String> {
+        [self]
+            .into_iter()
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .find_map(|config| config.aliases.get(name))
+    }
+
+    /// Returns whether a repository is excluded by this config, the default profile when a
+    /// profile is selected, or any project config.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let Ok(path) = path.absolutize() else {
+            return false;
+        };
+        [self]
+            .into_iter()
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .chain(&self.layers)
+            .chain(&self.directory_layers)
+            .any(|config| {
+                config
+                    .exclude
+                    .iter()
+                    .any(|exclude| path.starts_with(exclude))
+            })
+    }
+
+    /// Returns whether `path` is inside the directory containing this config file.
+    fn contains(&self, path: &Path) -> bool {
+        match (self.base_directory(), path.absolutize()) {
+            (Ok(base), Ok(path)) => path.starts_with(base),
+            _ => false,
+        }
+    }
+
+    /// Returns whether commands stop at the first failure, preferring the nearest project config.
+    pub fn fail_fast(&self) -> bool {
+        self.layers
+            .iter()
+            .chain([self])
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .find_map(|config| config.fail_fast)
+            .unwrap_or(false)
+    }
+
+    /// Returns how long the daemon waits between fetches, 15 minutes unless configured.
+    pub fn daemon_interval(&self) -> Result<Duration> {
+        let interval = [self]
+            .into_iter()
+            .chain(self.parent.as_ref().map(|(_, parent)| parent.as_ref()))
+            .find_map(|config| config.daemon.as_ref()?.interval.as_ref());
+        match interval {
+            Some(interval) => humantime::parse_duration(interval)
+                .with_context(|| format!("Invalid daemon interval `{}`", interval)),
+            None => Ok(Duration::from_secs(15 * 60)),
+        }
+    }
+
     /// Registers a path as a repository or directory.
     ///
     /// If the path is a Git repository, it is added to the repositories map.
     /// If the path is a directory containing repositories, it is added to the directories map.
     pub fn register(&mut self, path: &Path) -> Result<()> {
-        let absolute_path = path.absolutize().context("Failed to get absolute path")?;
-        let name = absolute_path
-            .to_str()
-            .context("Failed to convert path to string")?;
-
-        if !is_git_repository(path) {
+        let absolute_path = path
+            .absolutize()
+            .context("Failed to get absolute path")?
+            .to_path_buf();
+        self.remove_entries(&absolute_path);
+        let name = self.written_path(&absolute_path)?;
+
+        if !is_git_repository(&absolute_path) {
             let entry = DirectoryEntry {
-                path: path.to_path_buf(),
+                path: absolute_path,
             };
-            self.directories.insert(name.to_string(), entry);
+            self.directories.insert(name, entry);
         } else {
-            let entry = RepositoryEntry {
-                path: path.to_path_buf(),
-            };
-            self.repositories.insert(name.to_string(), entry);
+            let mut entry = RepositoryEntry::new(absolute_path);
+            entry.remote_url = entry.tracking_remote_url().ok();
+            self.repositories.insert(name, entry);
         }
         self.save()?;
         anyhow::Ok(())
@@ -239,14 +1077,56 @@ impl Config {
     /// Unregisters a repository or directory.
     pub fn unregister(&mut self, path: &PathBuf) -> Result<()> {
         let absolute_path = path.absolutize().context("Failed to get absolute path")?;
-        let name = absolute_path
-            .to_str()
-            .context("Failed to convert path to string")?;
-        self.directories.remove(name);
-        self.repositories.remove(name);
+        self.remove_entries(&absolute_path);
         self.save()?;
         anyhow::Ok(())
     }
+
+    /// Removes the repository and directory entries for an absolute path.
+    fn remove_entries(&mut self, path: &Path) {
+        self.repositories.retain(|_, entry| entry.path != path);
+        self.directories.retain(|_, entry| entry.path != path);
+    }
+}
+
+/// Resolves a path written in the config file to an absolute path.
+///
+/// Expands `~` and environment variables, and resolves relative paths against `base`. A path
+/// naming an undefined variable is used as written.
+fn resolve_config_path(written: &str, base: &Path) -> PathBuf {
+    let expanded = match shellexpand::full(written) {
+        Ok(expanded) => expanded.to_string(),
+        Err(e) => {
+            log::warn!("Failed to expand `{}` in the config: {}", written, e);
+            written.to_string()
+        }
+    };
+    Path::new(&expanded)
+        .absolutize_from(base)
+        .map(|path| path.to_path_buf())
+        .unwrap_or_else(|_| PathBuf::from(expanded))
+}
+
+/// Returns `path` relative to `base`, using `..` where needed. Both paths must be absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
 }
 
 /// Represents the main application handling multiple repositories.
@@ -259,10 +1139,33 @@ pub struct Multigit {
 
     /// The stylesheet used for colored output.
     pub style_sheet: StyleSheet<'static>,
+
+    /// The command-line arguments of this run, recorded in the last-run state for `rerun`.
+    pub invocation: Vec<String>,
+
+    /// The directory for state files such as the last-run results. Nothing is recorded if `None`.
+    pub state_directory: Option<PathBuf>,
+
+    /// If set, only repositories at these absolute paths are selected.
+    pub only: Option<Vec<PathBuf>>,
+
+    /// Stop processing repositories after the first failure.
+    pub fail_fast: bool,
+
+    /// Whether repositories were named on the command line, which overrides default filters.
+    pub named: bool,
+
+    /// Interactively pick from the selected repositories before the command runs.
+    pub pick: bool,
+
+    /// The repositories picked interactively, so the picker is only shown once per run.
+    picked: OnceCell<Vec<PathBuf>>,
 }
 
 impl Multigit {
     /// Creates a new instance of `Multigit`.
+    ///
+    /// Only the project configs already loaded into `config` with `Config::load_layers` are used.
     pub fn new(config: Config, directory: Option<PathBuf>) -> Result<Self> {
         let style_sheet = StyleSheet::parse(
             "
@@ -278,101 +1181,352 @@ impl Multigit {
             config,
             directory,
             style_sheet,
+            invocation: Vec::new(),
+            state_directory: None,
+            only: None,
+            fail_fast: false,
+            named: false,
+            pick: false,
+            picked: OnceCell::new(),
         })
     }
 
-    /// Retrieves all repositories, optionally filtering them.
-    fn all_repositories(&self, filter: Option<&Vec<Filter>>) -> Result<Vec<RepositoryEntry>> {
-        let mut repositories: Vec<RepositoryEntry> = Vec::new();
+    /// Returns the path of a state file, if a state directory is configured.
+    fn state_path(&self, file_name: &str) -> Option<PathBuf> {
+        self.state_directory
+            .as_ref()
+            .map(|directory| directory.join(file_name))
+    }
 
-        if self.directory.is_some() {
-            let directory = self.directory.as_ref().unwrap();
-            let directory_repositories = find_repositories(directory)?;
-            let mut repositories: Vec<RepositoryEntry> = Vec::new();
-            for repository in directory_repositories {
-                let repository = RepositoryEntry { path: repository };
-                repositories.push(repository);
-            }
-            return Ok(repositories);
-        } else {
-            for (_, repository) in self.config.repositories.iter() {
-                repositories.push(RepositoryEntry {
-                    path: repository.path.clone(),
-                });
-            }
-            for (_, directory) in self.config.directories.iter() {
-                let directory_repositories = find_repositories(&directory.path)?;
-                for repository in directory_repositories {
-                    let repository = RepositoryEntry { path: repository };
-                    repositories.push(repository);
-                }
-            }
-        }
-
-        if let Some(filter) = filter {
-            if !filter.is_empty() {
-                repositories.retain(|repository| {
-                    for f in filter {
-                        match f {
-                            Filter::Dirty => {
-                                if repository
-                                    .state()
-                                    .unwrap()
-                                    .entries
-                                    .contains(&EntryState::Dirty)
-                                {
-                                    return true;
-                                }
-                            }
-                            Filter::Tracking => {
-                                if repository.has_tracking_branch().unwrap() {
-                                    return true;
-                                }
-                            }
-                        }
-                    }
-                    false
-                });
-            }
+    /// Returns the path of a state file kept separately for each profile, such as the last run.
+    ///
+    /// Profiles other than the default one add their name before the extension, e.g.
+    /// `last-run.work.toml`.
+    pub fn profile_state_path(&self, file_name: &str) -> Option<PathBuf> {
+        let profile = self.config.profile_name();
+        if profile == DEFAULT_PROFILE {
+            return self.state_path(file_name);
         }
-        repositories.sort_by(|a, b| a.path.cmp(&b.path));
-        anyhow::Ok(repositories)
+        let file_name = Path::new(file_name);
+        let extension = file_name.extension().unwrap_or_default().to_string_lossy();
+        self.state_path(
+            file_name
+                .with_extension(format!("{}.{}", profile, extension))
+                .to_str()?,
+        )
     }
 
-    #[allow(dead_code)]
-    fn iter_repositories(
-        &self,
+    /// Retrieves all repositories, optionally filtering them.
+    ///
+    /// Repositories are deduplicated, restricted to `only` and excluded ones are skipped. If
+    /// `pick` is set, the user is asked to choose from them.
+    pub fn all_repositories(&self, filter: Option<&Vec<Filter>>) -> Result<Vec<RepositoryEntry>> {
+        self.select_repositories(|_| filter.cloned().unwrap_or_default())
+    }
+
+    /// Retrieves all repositories that match any of the filters returned for them by
+    /// `filters_for`, and then asks the user to pick from them if `pick` is set. A repository with
+    /// no filters is always selected.
+    fn select_repositories<F>(&self, filters_for: F) -> Result<Vec<RepositoryEntry>>
+    where
+        F: Fn(&RepositoryEntry) -> Vec<Filter>,
+    {
+        let mut repositories = self.filter_repositories(filters_for)?;
+
+        if self.pick {
+            let picked = match self.picked.get() {
+                Some(picked) => picked,
+                None => {
+                    let picked = self.pick_repositories(&repositories)?;
+                    self.picked.get_or_init(|| picked)
+                }
+            };
+            repositories.retain(|repository| {
+                repository
+                    .path
+                    .absolutize()
+                    .map(|path| picked.iter().any(|picked| picked == &path))
+                    .unwrap_or(false)
+            });
+        }
+
+        anyhow::Ok(repositories)
+    }
+
+    /// Retrieves all repositories that match any of the filters returned for them by
+    /// `filters_for`, without picking from them.
+    fn filter_repositories<F>(&self, filters_for: F) -> Result<Vec<RepositoryEntry>>
+    where
+        F: Fn(&RepositoryEntry) -> Vec<Filter>,
+    {
+        let mut repositories: Vec<RepositoryEntry> = Vec::new();
+
+        if let Some(directory) = &self.directory {
+            let directory_repositories = find_repositories(directory)?;
+            for repository in directory_repositories {
+                let mut repository = RepositoryEntry::new(repository);
+                repository.sources.push("--directory".to_string());
+                repositories.push(repository);
+            }
+        } else {
+            config_repositories(&self.config, "", &mut repositories)?;
+            for layer in &self.config.layers {
+                let prefix = match &layer.path {
+                    Some(path) => format!("{}: ", path.display()),
+                    None => String::new(),
+                };
+                config_repositories(layer, &prefix, &mut repositories)?;
+            }
+        }
+
+        // The same repository can be registered directly and found under a registered directory,
+        // or be reached through a symlink or another worktree. Keep the first entry for each.
+        let mut unique: Vec<RepositoryEntry> = Vec::new();
+        let mut indices: HashMap<PathBuf, usize> = HashMap::new();
+        for repository in repositories {
+            match indices.entry(repository_identity(&repository.path)) {
+                std::collections::hash_map::Entry::Occupied(entry) => {
+                    unique[*entry.get()].sources.extend(repository.sources);
+                }
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(unique.len());
+                    unique.push(repository);
+                }
+            }
+        }
+        let mut repositories = unique;
+
+        if let Some(only) = &self.only {
+            repositories.retain(|repository| {
+                repository
+                    .path
+                    .absolutize()
+                    .map(|path| only.iter().any(|only| only == &path))
+                    .unwrap_or(false)
+            });
+        }
+
+        repositories.retain(|repository| !self.config.is_excluded(&repository.path));
+
+        repositories.retain(|repository| {
+            let filters = filters_for(repository);
+            filters.is_empty() || filters.iter().any(|filter| repository.matches(filter))
+        });
+        repositories.sort_by(|a, b| a.path.cmp(&b.path));
+        anyhow::Ok(repositories)
+    }
+
+    /// Asks the user to pick from `repositories`, and records the choice as the last selection.
+    ///
+    /// Returns the absolute paths of the picked repositories. The previous selection is
+    /// preselected.
+    fn pick_repositories(&self, repositories: &[RepositoryEntry]) -> Result<Vec<PathBuf>> {
+        if repositories.is_empty() {
+            return Ok(Vec::new());
+        }
+        let paths = repositories
+            .iter()
+            .map(|repository| Ok(repository.path.absolutize()?.to_path_buf()))
+            .collect::<Result<Vec<PathBuf>>>()?;
+
+        let selection_path = self.profile_state_path(LastSelection::FILE_NAME);
+        let last_selection = match &selection_path {
+            Some(path) => LastSelection::load(path)?,
+            None => None,
+        };
+        let defaults: Vec<usize> = match &last_selection {
+            Some(last_selection) => paths
+                .iter()
+                .enumerate()
+                .filter(|(_, path)| last_selection.paths.contains(path))
+                .map(|(index, _)| index)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let options: Vec<String> = repositories
+            .iter()
+            .map(|repository| format!("{} ({})", repository.name(), repository.path.display()))
+            .collect();
+        let picked: Vec<PathBuf> = MultiSelect::new("Select repositories:", options)
+            .with_default(&defaults)
+            .with_page_size(15)
+            .raw_prompt()?
+            .into_iter()
+            .map(|option| paths[option.index].clone())
+            .collect();
+
+        if let Some(path) = &selection_path {
+            LastSelection {
+                paths: picked.clone(),
+            }
+            .save(path)?;
+        }
+        Ok(picked)
+    }
+
+    /// Retrieves the repositories a command should run on.
+    ///
+    /// Explicit filters take precedence. Otherwise the command's default filters are used,
+    /// unless `all` is set or repositories were named on the command line.
+    pub fn command_repositories(
+        &self,
+        command: &str,
         filter: Option<&Vec<Filter>>,
-    ) -> Result<impl Iterator<Item = RepositoryEntry>> {
-        let repositories = self.all_repositories(filter)?;
-        Ok(repositories.into_iter())
+        all: &bool,
+    ) -> Result<Vec<RepositoryEntry>> {
+        if filter.is_some() || *all || self.named {
+            return self.all_repositories(filter);
+        }
+        self.select_repositories(|repository| {
+            self.config.default_filters_for(command, &repository.path)
+        })
+    }
+
+    /// Resolves repository names given on the command line to absolute paths.
+    ///
+    /// Each name matches a repository's alias or directory name exactly, or else a unique prefix
+    /// of one of them, or else a unique fuzzy match. Names that match nothing or more than one
+    /// repository are an error. `pick` is not applied, so the picker only offers the named
+    /// repositories.
+    pub fn resolve_repositories(&self, names: &[String]) -> Result<Vec<PathBuf>> {
+        let repositories = self.filter_repositories(|_| Vec::new())?;
+        let mut paths = Vec::new();
+        for name in names {
+            let repository = resolve_repository(&repositories, name)?;
+            let path = repository.path.absolutize()?.to_path_buf();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Returns a summary of each selected repository's state.
+    pub fn summaries(&self, filter: Option<&Vec<Filter>>) -> Result<Vec<RepositorySummary>> {
+        let fetch_state = self.fetch_state();
+        self.all_repositories(filter)?
+            .into_iter()
+            .map(|repository| self.summary_with(repository, &fetch_state))
+            .collect()
+    }
+
+    /// Returns a summary of a repository's state.
+    pub fn summary(&self, repository: RepositoryEntry) -> Result<RepositorySummary> {
+        self.summary_with(repository, &self.fetch_state())
     }
 
-    fn process_repositories<F>(
+    fn summary_with(
         &self,
-        repositories: &[RepositoryEntry],
-        mut process: F,
-    ) -> Result<()>
+        repository: RepositoryEntry,
+        fetch_state: &FetchState,
+    ) -> Result<RepositorySummary> {
+        Ok(RepositorySummary {
+            name: repository.name(),
+            branch: repository.current_branch().ok(),
+            state: repository.state().ok(),
+            ahead: repository.ahead_remote().ok().flatten(),
+            behind: repository.behind_remote().ok().flatten(),
+            has_stashes: repository.has_stashes().unwrap_or(false),
+            last_fetch: fetch_state.get(&repository).cloned(),
+            entry: repository,
+        })
+    }
+
+    /// Runs `process` in each repository and reports the results, without printing anything.
+    ///
+    /// Stops after the first failure if `fail_fast` is set.
+    pub fn run_repositories<F>(&self, repositories: &[RepositoryEntry], mut process: F) -> RunReport
     where
         F: FnMut(&RepositoryEntry) -> Result<()>,
     {
-        let mut errors = Vec::new();
-
+        let mut report = RunReport {
+            total: repositories.len(),
+            ..RunReport::default()
+        };
         for repository in repositories {
-            if let Err(e) = process(repository) {
+            let path = repository
+                .path
+                .absolutize()
+                .map(|path| path.to_path_buf())
+                .unwrap_or_else(|_| repository.path.clone());
+            match process(repository) {
+                Ok(()) => report.results.push(RepositoryResult { path, error: None }),
+                Err(e) => {
+                    report.results.push(RepositoryResult {
+                        path,
+                        error: Some(e.to_string()),
+                    });
+                    report.errors.push(RepositoryError {
+                        path: repository.path.clone(),
+                        error: e,
+                    });
+                    if self.fail_fast {
+                        break;
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    /// Runs `process` in each repository like `run_repositories`, printing errors as they occur.
+    fn run_printing_errors<F>(&self, repositories: &[RepositoryEntry], mut process: F) -> RunReport
+    where
+        F: FnMut(&RepositoryEntry) -> Result<()>,
+    {
+        self.run_repositories(repositories, |repository| {
+            let result = process(repository);
+            if let Err(e) = &result {
                 eprintln!("Error processing repository {:?}: {}", repository.path, e);
-                errors.push(RepositoryError {
-                    path: repository.path.clone(),
-                    error: e,
-                });
             }
+            result
+        })
+    }
+
+    /// Runs a read-only `process` such as `status` in each repository, printing errors as they
+    /// occur. Unlike `process_repositories`, the run is not recorded, so it doesn't replace the
+    /// run that `multigit rerun` repeats. Selecting no repositories isn't an error, as it is a
+    /// normal answer to a query such as `status --filter dirty`.
+    fn inspect_repositories<F>(&self, repositories: &[RepositoryEntry], process: F) -> Result<()>
+    where
+        F: FnMut(&RepositoryEntry) -> Result<()>,
+    {
+        let report = self.run_printing_errors(repositories, process);
+        if report.total == 0 {
+            return Ok(());
         }
+        report.into_result()
+    }
 
-        if errors.is_empty() {
-            anyhow::Ok(())
-        } else {
-            Err(anyhow!("Errors occurred in {} repositories", errors.len()))
+    /// Runs `process` in each repository like `run_repositories`, printing errors as they occur
+    /// and recording the results for `multigit last` and `rerun`.
+    fn process_repositories<F>(&self, repositories: &[RepositoryEntry], process: F) -> Result<()>
+    where
+        F: FnMut(&RepositoryEntry) -> Result<()>,
+    {
+        // Taken before the run, so that neither the run nor a failure here affects the other.
+        let working_directory = env::current_dir();
+        let report = self.run_printing_errors(repositories, process);
+
+        if let Some(last_run_path) = self.profile_state_path(LastRun::FILE_NAME) {
+            let saved = working_directory
+                .map_err(anyhow::Error::from)
+                .and_then(|directory| {
+                    LastRun {
+                        invocation: self.invocation.clone(),
+                        working_directory: directory,
+                        finished: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                        results: report.results.clone(),
+                    }
+                    .save(&last_run_path)
+                });
+            if let Err(e) = saved {
+                log::warn!("Failed to save last run: {}", e);
+            }
         }
+
+        report.into_result()
     }
 
     /// Registers paths as repositories or directories.
@@ -414,41 +1568,289 @@ impl Multigit {
         anyhow::Ok(())
     }
 
+    /// Checks that every registered repository and directory still exists.
+    ///
+    /// With `prune`, entries that no longer exist are removed from the config, and remote URLs
+    /// are recorded for entries registered without one. Otherwise the config is left unchanged.
+    /// For a missing repository, a repository with the same remote URL under a registered
+    /// directory is suggested as its new location.
+    pub fn doctor(&mut self, prune: &bool) -> Result<()> {
+        // Repository and directory keys are separate, so the same key can appear in both.
+        let mut repository_problems = Vec::new();
+        let mut directory_problems = Vec::new();
+        let mut changed = false;
+
+        let mut keys: Vec<String> = self.config.repositories.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let entry = &self.config.repositories[&key];
+            let problem = if !entry.path.exists() {
+                "missing"
+            } else if !is_git_repository(&entry.path) {
+                "not a git repository"
+            } else {
+                // Record remote URLs for entries registered before they were tracked, so
+                // later moves can be detected. Only `--prune` writes the config.
+                if *prune && entry.remote_url.is_none() {
+                    if let Ok(url) = entry.tracking_remote_url() {
+                        self.config.repositories.get_mut(&key).unwrap().remote_url = Some(url);
+                        changed = true;
+                    }
+                }
+                continue;
+            };
+            println_markup!(
+                &self.style_sheet,
+                "<repository>{}</repository>: <status>{}</status>",
+                entry.path.display(),
+                problem
+            );
+            if let Some(location) = self.suggest_location(entry) {
+                println!(
+                    "  It may have moved to {}. Run `multigit relocate {} {}` to update it.",
+                    location.display(),
+                    shell_words::quote(&entry.path.to_string_lossy()),
+                    shell_words::quote(&location.to_string_lossy())
+                );
+            }
+            repository_problems.push(key);
+        }
+
+        let mut keys: Vec<String> = self.config.directories.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let entry = &self.config.directories[&key];
+            let problem = if !entry.path.exists() {
+                "missing"
+            } else if !entry.path.is_dir() {
+                "not a directory"
+            } else {
+                continue;
+            };
+            println_markup!(
+                &self.style_sheet,
+                "<repository>{}</repository>: <status>{}</status>",
+                entry.path.display(),
+                problem
+            );
+            directory_problems.push(key);
+        }
+
+        let problems = repository_problems.len() + directory_problems.len();
+        if problems == 0 {
+            println!("No problems found.");
+        } else if *prune {
+            for key in &repository_problems {
+                self.config.repositories.remove(key);
+            }
+            for key in &directory_problems {
+                self.config.directories.remove(key);
+            }
+            println!("Removed {} entries from the config.", problems);
+            changed = true;
+        }
+        if changed {
+            self.config.save()?;
+        }
+        if problems > 0 && !*prune {
+            return Err(anyhow!(
+                "Found {} problem{}. Run `multigit doctor --prune` to remove the entries.",
+                problems,
+                if problems == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
+    }
+
+    /// Looks under the registered directories for where a missing repository may have moved.
+    ///
+    /// Matches by the recorded remote URL, or by directory name if no URL was recorded.
+    fn suggest_location(&self, entry: &RepositoryEntry) -> Option<PathBuf> {
+        let registered: HashSet<&PathBuf> = self
+            .config
+            .repositories
+            .values()
+            .map(|repository| &repository.path)
+            .collect();
+        self.config
+            .directories
+            .values()
+            .filter(|directory| directory.path.is_dir())
+            .flat_map(|directory| find_repositories(&directory.path).unwrap_or_default())
+            .filter(|path| !registered.contains(path))
+            .find(|path| match &entry.remote_url {
+                Some(url) => {
+                    RepositoryEntry::new(path.clone())
+                        .tracking_remote_url()
+                        .ok()
+                        .as_ref()
+                        == Some(url)
+                }
+                None => path.file_name() == entry.path.file_name(),
+            })
+    }
+
+    /// Lists the profiles, marking the one in use.
+    pub fn profile_list(&self) -> Result<()> {
+        for name in self.config.profile_names() {
+            if name == self.config.profile_name() {
+                println_markup!(&self.style_sheet, "* <repository>{}</repository>", name);
+            } else {
+                println!("  {}", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the profile used when none is given on the command line.
+    pub fn profile_use(&mut self, name: &str) -> Result<()> {
+        self.config.set_default_profile(name)?;
+        println_markup!(
+            &self.style_sheet,
+            "Using profile <repository>{}</repository>",
+            name
+        );
+        Ok(())
+    }
+
+    /// Sets metadata on a repository from `key=value` pairs. An empty value removes the key.
+    ///
+    /// A repository found under a registered directory is registered directly to hold it.
+    pub fn repo_set(&mut self, name: &String, values: &[String]) -> Result<()> {
+        let path = self
+            .resolve_repositories(std::slice::from_ref(name))?
+            .remove(0);
+        let mut changes = Vec::new();
+        for value in values {
+            let (key, value) = value
+                .split_once('=')
+                .with_context(|| format!("Expected KEY=VALUE, got `{}`", value))?;
+            if key.is_empty() {
+                return Err(anyhow!("Expected KEY=VALUE, got `={}`", value));
+            }
+            changes.push((key, (!value.is_empty()).then(|| value.to_string())));
+        }
+
+        let key = match self
+            .config
+            .repositories
+            .iter()
+            .find(|(_, entry)| entry.path == path)
+        {
+            Some((key, _)) => key.clone(),
+            None => {
+                let key = self.config.written_path(&path)?;
+                let mut entry = RepositoryEntry::new(path.clone());
+                entry.remote_url = entry.tracking_remote_url().ok();
+                self.config.repositories.insert(key.clone(), entry);
+                key
+            }
+        };
+        let entry = self.config.repositories.get_mut(&key).unwrap();
+        for (key, value) in changes {
+            entry.set_metadata_value(key, value);
+        }
+        self.config.save()?;
+        Ok(())
+    }
+
+    /// Points the config entry for a moved repository or directory at its new location.
+    pub fn relocate(&mut self, old: &Path, new: &Path) -> Result<()> {
+        let old = old.absolutize()?.to_path_buf();
+        let new = new.absolutize()?.to_path_buf();
+        let written = self.config.relocated_written_path(&old, &new)?;
+
+        let repository_key = self
+            .config
+            .repositories
+            .iter()
+            .find_map(|(key, entry)| (entry.path == old).then(|| key.clone()));
+        let directory_key = self
+            .config
+            .directories
+            .iter()
+            .find_map(|(key, entry)| (entry.path == old).then(|| key.clone()));
+
+        if let Some(key) = repository_key {
+            if !is_git_repository(&new) {
+                return Err(anyhow!("{} is not a git repository", new.display()));
+            }
+            // Keep the key, which may be a name the user chose, and only move the path.
+            let entry = self.config.repositories.get_mut(&key).unwrap();
+            entry.path = new.clone();
+            entry.remote_url = entry
+                .tracking_remote_url()
+                .ok()
+                .or(entry.remote_url.clone());
+        } else if let Some(key) = directory_key {
+            if !new.is_dir() {
+                return Err(anyhow!("{} is not a directory", new.display()));
+            }
+            self.config.directories.get_mut(&key).unwrap().path = new.clone();
+        } else {
+            return Err(anyhow!(
+                "No repository or directory is registered at {}",
+                old.display()
+            ));
+        }
+        self.config.written_paths.insert(new.clone(), written);
+        self.config.save()?;
+        println!("Relocated {} to {}", old.display(), new.display());
+        Ok(())
+    }
+
     /// Lists all registered repositories.
     pub fn list(&self, filter: Option<&Vec<Filter>>, detailed: &bool) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
+        let summaries = self.summaries(filter)?;
 
         #[derive(Tabled)]
         struct Row<'a> {
-            name: String,
+            name: &'a str,
             #[tabled(skip)]
             path: Display<'a>,
-            state: RepositoryState,
+            #[tabled(display_with = "display_option")]
+            state: Option<RepositoryState>,
             current_branch: String,
             #[tabled(display_with = "display_option")]
             behind_remote: Option<bool>,
             #[tabled(display_with = "display_option")]
             ahead_remote: Option<bool>,
             has_stashes: bool,
+            last_fetch: String,
+            alias: String,
+            description: String,
+            owner: String,
+            default_branch: String,
+            metadata: String,
+            sources: String,
         }
 
-        let rows = repositories.iter().map(|repository| {
-            let name = repository
-                .path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
-            let path = repository.path.display();
+        let rows = summaries.iter().map(|summary| {
+            let repository = &summary.entry;
             Row {
-                name,
-                path,
-                state: repository.state().unwrap(),
-                current_branch: repository.current_branch().unwrap(),
-                behind_remote: repository.behind_remote().ok().flatten(),
-                ahead_remote: repository.ahead_remote().ok().flatten(),
-                has_stashes: repository.has_stashes().unwrap(),
+                name: &summary.name,
+                path: repository.path.display(),
+                state: summary.state.clone(),
+                current_branch: summary.branch.clone().unwrap_or_default(),
+                behind_remote: summary.behind,
+                ahead_remote: summary.ahead,
+                has_stashes: summary.has_stashes,
+                last_fetch: summary
+                    .last_fetch
+                    .as_ref()
+                    .map(|record| record.to_string())
+                    .unwrap_or_default(),
+                alias: repository.alias.clone().unwrap_or_default(),
+                description: repository.description.clone().unwrap_or_default(),
+                owner: repository.owner.clone().unwrap_or_default(),
+                default_branch: repository.default_branch.clone().unwrap_or_default(),
+                metadata: repository
+                    .metadata
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                sources: repository.sources.join("\n"),
             }
         });
 
@@ -467,7 +1869,8 @@ impl Multigit {
     /// Shows the status of all repositories.
     pub fn status(&self, filter: Option<&Vec<Filter>>) -> Result<()> {
         let repositories = self.all_repositories(filter)?;
-        self.process_repositories(&repositories, |repository| {
+        let fetch_state = self.fetch_state();
+        self.inspect_repositories(&repositories, |repository| {
             let mut status_options = git2::StatusOptions::new();
             status_options.include_untracked(true);
             status_options.include_ignored(false);
@@ -543,6 +1946,9 @@ impl Multigit {
                 if conflicted {
                     status_string.push_str(" [conflicted]");
                 }
+                if let Some(record) = fetch_state.get(repository) {
+                    status_string.push_str(&format!(" [{}]", record));
+                }
 
                 println_markup!(
                     &self.style_sheet,
@@ -578,18 +1984,89 @@ impl Multigit {
     }
 
     /// Executes a custom command in the selected repositories.
-    pub fn exec(&self, filter: Option<&Vec<Filter>>, commands: &[String]) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
-        self.process_repositories(&repositories, |repository| {
-            let mut command = std::process::Command::new(&commands[0]);
-            command.args(&commands[1..]);
+    ///
+    /// Placeholders such as `{name}` and `{path}` are expanded per repository. If `shell` is set,
+    /// the command is joined and run through `sh -c`, so pipes and redirections work.
+    ///
+    /// If `capture` is set (or a `log_dir` is given), output lines are prefixed with the
+    /// repository name, full logs are written to `log_dir` and a summary table is printed.
+    pub fn exec(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        shell: &bool,
+        capture: &bool,
+        log_dir: &Option<PathBuf>,
+        commands: &[String],
+    ) -> Result<()> {
+        if commands.is_empty() {
+            return Err(anyhow!("No command given"));
+        }
+        let repositories = self.command_repositories("exec", filter, all)?;
+        let capture = *capture || log_dir.is_some();
+        if let Some(log_dir) = log_dir {
+            fs::create_dir_all(log_dir)?;
+        }
+        let log_names = log_file_names(&repositories);
+        let prefix_width = repositories
+            .iter()
+            .map(|repository| repository.name().len())
+            .max()
+            .unwrap_or(0);
+        let mut results = Vec::new();
+
+        let result = self.process_repositories(&repositories, |repository| {
+            let mut command = if *shell {
+                let mut command = std::process::Command::new("sh");
+                command.arg("-c");
+                command.arg(repository.expand_shell_template(&commands.join(" "))?);
+                command
+            } else {
+                let commands = commands
+                    .iter()
+                    .map(|arg| repository.expand_template(arg))
+                    .collect::<Result<Vec<String>>>()?;
+                let mut command = std::process::Command::new(&commands[0]);
+                command.args(&commands[1..]);
+                command
+            };
             command.current_dir(&repository.path);
-            let status = command.status()?;
+
+            let start = Instant::now();
+            let status = if capture {
+                let log_path = log_dir
+                    .as_ref()
+                    .map(|log_dir| log_dir.join(&log_names[&repository.path]));
+                run_captured(
+                    &mut command,
+                    &format!("{:width$}", repository.name(), width = prefix_width),
+                    log_path.as_deref(),
+                )
+            } else {
+                command.status().map_err(anyhow::Error::from)
+            };
+            let exit_code = status.as_ref().ok().and_then(|status| status.code());
+            results.push(ExecResult {
+                repository: repository.name(),
+                exit_code: exit_code.map_or("-".to_string(), |code| code.to_string()),
+                duration: format!("{:.2}s", start.elapsed().as_secs_f64()),
+            });
+
+            let status = status?;
             if !status.success() {
-                return Err(anyhow!("Failed to execute command"));
+                return Err(anyhow!(
+                    "Command `{}` failed with exit code {:?}",
+                    commands.join(" "),
+                    status.code()
+                ));
             }
             Ok(())
-        })
+        });
+
+        if capture {
+            println!("{}", Table::new(results));
+        }
+        result
     }
 
     /// Executes a Git command with optional arguments in the selected repositories.
@@ -597,9 +2074,27 @@ impl Multigit {
         &self,
         git_command: &str,
         repositories: &[RepositoryEntry],
+        retry_policy: RetryPolicy,
         passthrough: &[String],
     ) -> Result<()> {
-        let width = termsize::get().unwrap().cols as usize;
+        self.git_command_with_args(git_command, repositories, retry_policy, |repository| {
+            expand_arguments(repository, passthrough)
+        })
+    }
+
+    /// Executes a Git command in the selected repositories, building its arguments per repository.
+    fn git_command_with_args<F>(
+        &self,
+        git_command: &str,
+        repositories: &[RepositoryEntry],
+        retry_policy: RetryPolicy,
+        mut arguments: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&RepositoryEntry) -> Result<Vec<String>>,
+    {
+        // Without a terminal, e.g. when the output is piped, fall back to a common width.
+        let width = termsize::get().map_or(80, |size| size.cols as usize);
 
         let divider = "#".repeat(width);
 
@@ -616,102 +2111,779 @@ impl Multigit {
                 git_command,
                 repository.path.to_str().unwrap()
             );
-            let mut args = vec![git_command];
-            args.extend(passthrough.iter().map(|s| s.as_str()));
-            let mut command = std::process::Command::new("git");
-            command.args(&args);
-            command.current_dir(&repository.path);
-
-            // Execute the command and capture the status
-            let status = command.status()?;
+            let mut args = vec![git_command.to_string()];
+            args.extend(arguments(repository)?);
+
+            retry_policy.run(|| {
+                let mut command = std::process::Command::new("git");
+                command.args(&args);
+                command.current_dir(&repository.path);
+
+                // Execute the command and check if it was successful
+                let status = run_with_timeout(&mut command, retry_policy.timeout)?;
+                if !status.success() {
+                    return Err(GitFailed {
+                        command: git_command.to_string(),
+                        path: repository.path.clone(),
+                        code: status.code(),
+                    }
+                    .into());
+                }
+                Ok(())
+            })
+        })
+    }
 
-            // Check if the command was successful
-            if !status.success() {
-                return Err(anyhow!(
-                    "Git command {} failed in repository `{}` with exit code {:?}",
-                    git_command,
-                    repository.path.display(),
-                    status.code()
-                ));
+    /// Returns the retry policy for a command.
+    ///
+    /// Values given on the command line take precedence over the command's settings in the config file.
+    pub fn retry_policy(
+        &self,
+        command: &str,
+        timeout: &Option<Duration>,
+        retries: &Option<u32>,
+    ) -> Result<RetryPolicy> {
+        let settings = self.config.command_settings(command);
+        let timeout = match (timeout, settings.and_then(|s| s.timeout.as_ref())) {
+            (Some(timeout), _) => Some(*timeout),
+            (None, Some(timeout)) => {
+                Some(humantime::parse_duration(timeout).with_context(|| {
+                    format!("Invalid timeout `{}` for command `{}`", timeout, command)
+                })?)
             }
-            Ok(())
+            (None, None) => None,
+        };
+        let retries = retries.or(settings.and_then(|s| s.retries)).unwrap_or(0);
+        Ok(RetryPolicy {
+            timeout,
+            retries,
+            ..RetryPolicy::default()
         })
     }
 
     /// Commits changes in the selected repositories.
-    pub fn commit(&self, filter: Option<&Vec<Filter>>, passthrough: &[String]) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
-        self.git_command("commit", &repositories, passthrough)
+    ///
+    /// The message is asked for once (in `$EDITOR` if not given) and `{repo}` and `{branch}`
+    /// are expanded per repository.
+    pub fn commit(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        message: &Option<String>,
+        trailers: &[String],
+        change_set: &bool,
+        passthrough: &[String],
+    ) -> Result<()> {
+        let repositories = self.command_repositories("commit", filter, all)?;
+        if repositories.is_empty() {
+            return Err(RunError::NothingToDo.into());
+        }
+
+        let message = match message {
+            Some(message) => message.clone(),
+            None => edit_commit_message(&repositories)?,
+        };
+
+        let mut trailers = trailers.to_vec();
+        if *change_set {
+            trailers.push(format!("Change-Set: {}", uuid::Uuid::new_v4()));
+        }
+        let message = if trailers.is_empty() {
+            message
+        } else {
+            format!("{}\n\n{}", message.trim_end(), trailers.join("\n"))
+        };
+
+        self.git_command_with_args(
+            "commit",
+            &repositories,
+            RetryPolicy::default(),
+            |repository| {
+                let mut args = vec!["-m".to_string(), repository.expand_template(&message)?];
+                args.extend(expand_arguments(repository, passthrough)?);
+                Ok(args)
+            },
+        )
     }
 
     /// Adds files to the staging area in the selected repositories.
-    pub fn add(&self, filter: Option<&Vec<Filter>>, passthrough: &[String]) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
-        self.git_command("add", &repositories, passthrough)
+    pub fn add(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        passthrough: &[String],
+    ) -> Result<()> {
+        let repositories = self.command_repositories("add", filter, all)?;
+        self.git_command("add", &repositories, RetryPolicy::default(), passthrough)
     }
 
     /// Pushes changes to remote repositories.
-    pub fn push(&self, filter: Option<&Vec<Filter>>, passthrough: &[String]) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
-
-        self.git_command("push", &repositories, passthrough)
+    pub fn push(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        timeout: &Option<Duration>,
+        retries: &Option<u32>,
+        passthrough: &[String],
+    ) -> Result<()> {
+        let repositories = self.command_repositories("push", filter, all)?;
+        let retry_policy = self.retry_policy("push", timeout, retries)?;
+        self.git_command("push", &repositories, retry_policy, passthrough)
     }
 
-    /// Pulls changes from remote repositories.
-    pub fn pull(&self, filter: Option<&Vec<Filter>>, passthrough: &[String]) -> Result<()> {
+    /// Pulls changes from remote repositories, in those selected repositories that track one.
+    pub fn pull(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        timeout: &Option<Duration>,
+        retries: &Option<u32>,
+        passthrough: &[String],
+    ) -> Result<()> {
+        // Whatever the filters select, `git pull` can only run on a branch with an upstream.
         let repositories = self
-            .all_repositories(filter)?
+            .command_repositories("pull", filter, all)?
             .into_iter()
-            .filter(|repo| repo.has_tracking_branch().unwrap())
+            .filter(|repository| repository.has_tracking_branch().unwrap_or(false))
             .collect::<Vec<RepositoryEntry>>();
-        // let repositories = self.all_repositories(filter)?;
-
-        self.git_command("pull", &repositories, passthrough)
+        let retry_policy = self.retry_policy("pull", timeout, retries)?;
+        self.git_command("pull", &repositories, retry_policy, passthrough)
     }
 
     /// Fetchs changes from remote repositories.
-    pub fn fetch(&self, filter: Option<&Vec<Filter>>, passthrough: &[String]) -> Result<()> {
-        let repositories = self.all_repositories(filter)?;
-        self.git_command("fetch", &repositories, passthrough)
-    }
-
-    pub fn config(&self) -> Result<()> {
-        let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
-        let config_path = "~/.config/multigit/config.toml";
-        let config_path = shellexpand::tilde(config_path);
-        let full_command = format!("{} {}", editor, config_path);
-        let args = shell_words::split(&full_command)?;
-        let (cmd, args) = args.split_first().ok_or("Empty command").unwrap();
-        let status = Command::new(cmd).args(args).status()?;
-        if !status.success() {
-            return Err(anyhow!("Failed to execute command"));
-        }
-        Ok(())
+    pub fn fetch(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        timeout: &Option<Duration>,
+        retries: &Option<u32>,
+        passthrough: &[String],
+    ) -> Result<()> {
+        let repositories = self.command_repositories("fetch", filter, all)?;
+        let retry_policy = self.retry_policy("fetch", timeout, retries)?;
+        self.git_command("fetch", &repositories, retry_policy, passthrough)
     }
-}
 
-/// Enum representing possible filters for repositories.
-#[derive(clap::ValueEnum, Clone, Debug, Serialize)]
-pub enum Filter {
-    /// Filter repositories that have uncommitted changes.
-    Dirty,
+    /// Fetches changes from remote repositories using libgit2 instead of the `git` executable.
+    ///
+    /// Each repository fetches the remote its current branch tracks, or `origin`. Progress is
+    /// shown on a single line across all repositories when stderr is a terminal.
+    pub fn fetch_native(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        timeout: &Option<Duration>,
+        retries: &Option<u32>,
+    ) -> Result<()> {
+        let repositories = self.command_repositories("fetch", filter, all)?;
+        let retry_policy = self.retry_policy("fetch", timeout, retries)?;
+        set_network_timeout(retry_policy.timeout)?;
+        let show_progress = io::stderr().is_terminal();
+        let total = repositories.len();
+        let mut index = 0;
+        let mut fetched = 0;
+        let mut received_objects = 0;
+        let mut received_bytes = 0;
+
+        let result = self.process_repositories(&repositories, |repository| {
+            index += 1;
+            let prefix = format!("[{}/{}] {}", index, total, repository.name());
+            let stats = retry_policy.run(|| {
+                fetch_repository(repository, retry_policy.timeout, |stats| {
+                    if show_progress {
+                        eprint!(
+                            "\r\x1b[K{}: {}/{} objects, {}",
+                            prefix,
+                            stats.received_objects(),
+                            stats.total_objects(),
+                            format_bytes(stats.received_bytes())
+                        );
+                    }
+                })
+            });
+            if show_progress {
+                eprint!("\r\x1b[K");
+            }
+            self.record_fetch(repository, &stats);
+            let stats = stats?;
+            fetched += 1;
+            received_objects += stats.0;
+            received_bytes += stats.1;
+            println_markup!(
+                &self.style_sheet,
+                "{}: <status>{} objects, {}</status>",
+                prefix,
+                stats.0,
+                format_bytes(stats.1)
+            );
+            Ok(())
+        });
+
+        println!(
+            "Fetched {} repositories: {} objects, {}",
+            fetched,
+            received_objects,
+            format_bytes(received_bytes)
+        );
+        result
+    }
+
+    /// Fetches all registered repositories that have a remote every `interval`.
+    ///
+    /// The time and outcome of each fetch are recorded in the fetch state file, which `list`
+    /// and `status` read. Only one daemon can run per profile.
+    pub fn daemon(&self, interval: &Option<Duration>, once: &bool) -> Result<()> {
+        let _lock = self.lock_daemon()?;
+
+        let interval = match interval {
+            Some(interval) => *interval,
+            None => self.config.daemon_interval()?,
+        };
+        let retry_policy = self.retry_policy("fetch", &None, &None)?;
+        set_network_timeout(retry_policy.timeout)?;
+
+        loop {
+            let repositories = match self.all_repositories(Some(&vec![Filter::HasRemote])) {
+                Ok(repositories) => repositories,
+                Err(e) if *once => return Err(e),
+                Err(e) => {
+                    log::warn!("Failed to list repositories: {:#}", e);
+                    thread::sleep(interval);
+                    continue;
+                }
+            };
+            let mut failures = 0;
+            for repository in &repositories {
+                let result =
+                    retry_policy.run(|| fetch_repository(repository, retry_policy.timeout, |_| {}));
+                if let Err(e) = &result {
+                    failures += 1;
+                    log::warn!("Failed to fetch {}: {}", repository.path.display(), e);
+                }
+                self.record_fetch(repository, &result);
+            }
+            println!(
+                "{} Fetched {} repositories, {} failed",
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                repositories.len(),
+                failures
+            );
+
+            if *once {
+                return Ok(());
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Takes the lock that allows only one daemon per profile, held until the file is dropped.
+    ///
+    /// Each profile fetches its own repositories, so daemons for different profiles can run
+    /// side by side.
+    pub fn lock_daemon(&self) -> Result<fs::File> {
+        let lock_path = self
+            .profile_state_path(DAEMON_LOCK_FILE_NAME)
+            .context("No state directory configured")?;
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let lock_file = fs::File::create(&lock_path)?;
+        lock_file.try_lock_exclusive().map_err(|_| {
+            anyhow!(
+                "Another multigit daemon is already running for the `{}` profile",
+                self.config.profile_name()
+            )
+        })?;
+        Ok(lock_file)
+    }
+
+    /// Records the outcome of a fetch in the fetch state file.
+    fn record_fetch<T>(&self, repository: &RepositoryEntry, result: &Result<T>) {
+        let Some(path) = self.state_path(FetchState::FILE_NAME) else {
+            return;
+        };
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let saved = FetchState::load(&path).and_then(|mut state| {
+            state.record(repository, error)?;
+            state.save(&path)
+        });
+        if let Err(e) = saved {
+            log::warn!("Failed to save fetch state: {}", e);
+        }
+    }
+
+    /// Loads the fetch state file, or an empty state if there is none.
+    fn fetch_state(&self) -> FetchState {
+        self.state_path(FetchState::FILE_NAME)
+            .and_then(|path| FetchState::load(&path).ok())
+            .unwrap_or_default()
+    }
+
+    /// Shows the combined diff of all dirty repositories.
+    ///
+    /// If `output` is set, one patch file per repository is written into that directory instead.
+    pub fn diff(
+        &self,
+        filter: Option<&Vec<Filter>>,
+        staged: &bool,
+        stat: &bool,
+        output: &Option<PathBuf>,
+    ) -> Result<()> {
+        let repositories = self
+            .all_repositories(filter)?
+            .into_iter()
+            .filter(|repository| repository.is_dirty())
+            .collect::<Vec<RepositoryEntry>>();
+
+        if let Some(output) = output {
+            // Patches are named after their repositories, so two with the same name would
+            // overwrite each other.
+            let mut names = HashSet::new();
+            for repository in &repositories {
+                if !names.insert(repository.name()) {
+                    return Err(anyhow!(
+                        "Multiple repositories named `{}`, their patches would overwrite each other",
+                        repository.name()
+                    ));
+                }
+            }
+            fs::create_dir_all(output)?;
+        }
+
+        self.inspect_repositories(&repositories, |repository| {
+            let repo = git2::Repository::open(&repository.path)?;
+            let diff = repository_diff(&repo, *staged)?;
+            if diff.deltas().len() == 0 {
+                return Ok(());
+            }
+
+            if let Some(output) = output {
+                let patch_path = output.join(format!("{}.patch", repository.name()));
+                fs::write(&patch_path, diff_to_patch(&diff)?)?;
+                println_markup!(
+                    &self.style_sheet,
+                    "Wrote <repository>{}</repository> to {}",
+                    repository.path.to_str().unwrap(),
+                    patch_path.display()
+                );
+                return Ok(());
+            }
+
+            println_markup!(
+                &self.style_sheet,
+                "<repository>{}</repository>",
+                repository.path.to_str().unwrap()
+            );
+            if *stat {
+                let stats = diff.stats()?;
+                let buf = stats.to_buf(git2::DiffStatsFormat::FULL, 80)?;
+                print!("{}", String::from_utf8_lossy(&buf));
+            } else {
+                print!("{}", String::from_utf8_lossy(&diff_to_patch(&diff)?));
+            }
+            println!();
+            Ok(())
+        })
+    }
+
+    /// Applies patch files written by `diff` to the matching repositories.
+    ///
+    /// Each `<name>.patch` file in `patches` is applied to the repository whose directory name is `<name>`.
+    pub fn apply(&self, filter: Option<&Vec<Filter>>, patches: &Path, staged: &bool) -> Result<()> {
+        let repositories = self.all_repositories(filter)?;
+
+        let mut patch_paths = fs::read_dir(patches)
+            .with_context(|| format!("Failed to read patch directory {}", patches.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "patch")
+            })
+            .collect::<Vec<PathBuf>>();
+        patch_paths.sort();
+
+        let mut targets = Vec::new();
+        let mut patches_by_path = HashMap::new();
+        for patch_path in patch_paths {
+            let name = patch_path
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            let matches = repositories
+                .iter()
+                .filter(|repository| repository.name() == name)
+                .collect::<Vec<&RepositoryEntry>>();
+            let repository = match matches.as_slice() {
+                [repository] => repository,
+                [] => {
+                    return Err(anyhow!(
+                        "No repository named `{}` for {}",
+                        name,
+                        patch_path.display()
+                    ))
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Multiple repositories named `{}` for {}",
+                        name,
+                        patch_path.display()
+                    ))
+                }
+            };
+            patches_by_path.insert(repository.path.clone(), patch_path);
+            targets.push((*repository).clone());
+        }
+
+        let location = if *staged {
+            git2::ApplyLocation::Both
+        } else {
+            git2::ApplyLocation::WorkDir
+        };
+
+        self.process_repositories(&targets, |repository| {
+            let patch_path = &patches_by_path[&repository.path];
+            let repo = git2::Repository::open(&repository.path)?;
+            let diff = git2::Diff::from_buffer(&fs::read(patch_path)?)?;
+            repo.apply(&diff, location, None)?;
+            println_markup!(
+                &self.style_sheet,
+                "Applied {} to <repository>{}</repository>",
+                patch_path.display(),
+                repository.path.to_str().unwrap()
+            );
+            Ok(())
+        })
+    }
+
+    /// Returns the dotted keys of every value in the configuration, e.g. `daemon.interval`.
+    pub fn config_keys(&self) -> Result<Vec<String>> {
+        fn collect(prefix: &str, value: &toml::Value, keys: &mut Vec<String>) {
+            if let toml::Value::Table(table) = value {
+                for (key, value) in table {
+                    let key = if prefix.is_empty() {
+                        quote_config_key(key)
+                    } else {
+                        format!("{}.{}", prefix, quote_config_key(key))
+                    };
+                    keys.push(key.clone());
+                    collect(&key, value, keys);
+                }
+            }
+        }
+        let mut keys = Vec::new();
+        collect(
+            "",
+            &toml::Value::try_from(self.config.written_file()?)?,
+            &mut keys,
+        );
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Returns the names and aliases of all repositories, with the path each one selects.
+    pub fn repository_names(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut names = Vec::new();
+        for repository in self.filter_repositories(|_| Vec::new())? {
+            names.push((repository.name(), repository.path.clone()));
+            if let Some(alias) = &repository.alias {
+                names.push((alias.clone(), repository.path.clone()));
+            }
+        }
+        Ok(names)
+    }
+
+    /// Returns the local branch names that every repository has, sorted.
+    ///
+    /// Repositories that can't be opened are skipped.
+    pub fn common_branches(&self) -> Result<Vec<String>> {
+        let mut common: Option<BTreeSet<String>> = None;
+        for repository in self.filter_repositories(|_| Vec::new())? {
+            let Ok(repo) = git2::Repository::open(&repository.path) else {
+                continue;
+            };
+            let branches = repo
+                .branches(Some(git2::BranchType::Local))?
+                .filter_map(|branch| Some(branch.ok()?.0.name().ok()??.to_string()))
+                .collect::<BTreeSet<_>>();
+            common = Some(match common {
+                Some(common) => common.intersection(&branches).cloned().collect(),
+                None => branches,
+            });
+        }
+        Ok(common.unwrap_or_default().into_iter().collect())
+    }
+
+    /// Prints the absolute path of the repository matching `name`.
+    ///
+    /// With `list`, prints the name and alias of every repository instead, one per line, for
+    /// shell completion.
+    pub fn path(&self, name: Option<&String>, list: &bool) -> Result<()> {
+        if *list {
+            for (name, _) in self.repository_names()? {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+        let name = name.context("A repository name is required")?;
+        let paths = self.resolve_repositories(std::slice::from_ref(name))?;
+        println!("{}", paths[0].display());
+        Ok(())
+    }
+
+    /// Shows the per-repository results of the previous run.
+    pub fn last(&self) -> Result<()> {
+        let last_run_path = self
+            .profile_state_path(LastRun::FILE_NAME)
+            .context("No state directory configured")?;
+        let last_run = LastRun::load(&last_run_path)?.context("No previous run recorded")?;
+
+        #[derive(Tabled)]
+        struct Row {
+            repository: String,
+            result: String,
+        }
+
+        println_markup!(
+            &self.style_sheet,
+            "<command>multigit {}</command> finished at {}",
+            shell_words::join(&last_run.invocation),
+            last_run.finished
+        );
+        let rows = last_run.results.iter().map(|result| Row {
+            repository: result.path.display().to_string(),
+            result: result.error.clone().unwrap_or_else(|| "ok".to_string()),
+        });
+        println!("{}", Table::new(rows));
+        Ok(())
+    }
+
+    /// Opens the config file in `$EDITOR`.
+    ///
+    /// With `key`, prints the value at that dotted key instead, e.g. `daemon.interval`.
+    pub fn config(&self, key: Option<&String>) -> Result<()> {
+        if let Some(key) = key {
+            let mut value = toml::Value::try_from(self.config.written_file()?)?;
+            for segment in split_config_key(key) {
+                value = value
+                    .get(&segment)
+                    .cloned()
+                    .with_context(|| format!("No config value at `{}`", key))?;
+            }
+            match value {
+                toml::Value::String(string) => println!("{}", string),
+                toml::Value::Table(table) => print!("{}", toml::to_string(&table)?),
+                value => println!("{}", value),
+            }
+            return Ok(());
+        }
+        let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+        let config_path = match &self.config.path {
+            Some(path) => path.display().to_string(),
+            None => shellexpand::tilde("~/.config/multigit/config.toml").to_string(),
+        };
+        let full_command = format!("{} {}", editor, shell_words::quote(&config_path));
+        let args = shell_words::split(&full_command)?;
+        let (cmd, args) = args.split_first().ok_or("Empty command").unwrap();
+        let status = Command::new(cmd).args(args).status()?;
+        if !status.success() {
+            return Err(anyhow!("Failed to execute command"));
+        }
+        Ok(())
+    }
+}
+
+/// Enum representing possible filters for repositories.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Filter {
+    /// Filter repositories that have uncommitted changes.
+    Dirty,
+    /// Filter repositories that have changes staged for commit.
+    Staged,
     /// Filter where current branch is tracking remote
     Tracking,
+    /// Filter where current branch has commits not on its remote
+    Ahead,
+    /// Filter where current branch is missing commits from its remote
+    Behind,
+    /// Filter repositories that have at least one remote
+    HasRemote,
+    /// Filter repositories whose metadata `key` is `value`, written `key=value`
+    Metadata { key: String, value: String },
+}
+
+impl Filter {
+    /// The filters that are written as a single word, with their descriptions.
+    const NAMED: [(&'static str, Filter, &'static str); 6] = [
+        (
+            "dirty",
+            Filter::Dirty,
+            "Repositories that have uncommitted changes",
+        ),
+        (
+            "staged",
+            Filter::Staged,
+            "Repositories that have changes staged for commit",
+        ),
+        (
+            "tracking",
+            Filter::Tracking,
+            "Current branch is tracking remote",
+        ),
+        (
+            "ahead",
+            Filter::Ahead,
+            "Current branch has commits not on its remote",
+        ),
+        (
+            "behind",
+            Filter::Behind,
+            "Current branch is missing commits from its remote",
+        ),
+        (
+            "has-remote",
+            Filter::HasRemote,
+            "Repositories that have at least one remote",
+        ),
+    ];
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((_, filter, _)) = Filter::NAMED.iter().find(|(name, _, _)| *name == s) {
+            return Ok(filter.clone());
+        }
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Filter::Metadata {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(anyhow!(
+                "Unknown filter `{}`, expected one of {} or KEY=VALUE",
+                s,
+                Filter::NAMED.map(|(name, _, _)| name).join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Metadata { key, value } => write!(f, "{}={}", key, value),
+            filter => {
+                let (name, _, _) = Filter::NAMED
+                    .iter()
+                    .find(|(_, named, _)| named == filter)
+                    .unwrap();
+                write!(f, "{}", name)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+impl clap::builder::ValueParserFactory for Filter {
+    type Parser = FilterValueParser;
+
+    fn value_parser() -> Self::Parser {
+        FilterValueParser
+    }
+}
+
+/// Parses `--filter` values, offering the named filters for help and completion.
+#[derive(Clone, Debug)]
+pub struct FilterValueParser;
+
+impl clap::builder::TypedValueParser for FilterValueParser {
+    type Value = Filter;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Filter, clap::Error> {
+        value
+            .to_str()
+            .context("Filter is not valid UTF-8")
+            .and_then(Filter::from_str)
+            .map_err(|e| {
+                clap::Error::raw(clap::error::ErrorKind::InvalidValue, format!("{}\n", e))
+                    .with_cmd(cmd)
+            })
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(Filter::NAMED.iter().map(|(name, _, help)| {
+            clap::builder::PossibleValue::new(*name).help(*help)
+        })))
+    }
 }
 
 /// Enum representing the state of repository entries.
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize)]
 pub enum EntryState {
     /// Indicates that the repository has uncommitted changes.
     Dirty,
 }
 
 /// Represents the state of a repository.
+#[derive(Clone, Debug, Serialize)]
 pub struct RepositoryState {
     /// A set of entry states.
     pub entries: HashSet<EntryState>,
 }
 
+/// A snapshot of a repository's state together with its config entry, as returned by
+/// `Multigit::summaries`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositorySummary {
+    /// The repository's directory name.
+    pub name: String,
+
+    /// The repository's config entry, with its metadata and the sources it was found through.
+    #[serde(flatten)]
+    pub entry: RepositoryEntry,
+
+    /// The current branch, or `None` if it can't be read.
+    pub branch: Option<String>,
+
+    /// The state of the working tree, or `None` if it can't be read.
+    pub state: Option<RepositoryState>,
+
+    /// Whether the current branch has commits its upstream doesn't, or `None` without an upstream.
+    pub ahead: Option<bool>,
+
+    /// Whether the current branch is missing commits from its upstream, or `None` without an
+    /// upstream.
+    pub behind: Option<bool>,
+
+    /// Whether the repository has stashed changes.
+    pub has_stashes: bool,
+
+    /// The most recent background fetch, if one was recorded.
+    pub last_fetch: Option<FetchRecord>,
+}
+
 /// Opens the configured Git UI for a given repository path.
 pub fn open_in_git_ui(path: &Path) -> Result<()> {
     let editor = "gitup";
@@ -724,6 +2896,55 @@ pub fn open_in_git_ui(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Adds the repositories registered in `config`, directly or under a registered directory.
+///
+/// Each repository's sources name the config entries it was found through, prefixed with `prefix`.
+/// Entries that no longer exist are skipped with a warning.
+fn config_repositories(
+    config: &Config,
+    prefix: &str,
+    repositories: &mut Vec<RepositoryEntry>,
+) -> Result<()> {
+    let mut keys: Vec<&String> = config.repositories.keys().collect();
+    keys.sort();
+    for key in keys {
+        let repository = &config.repositories[key];
+        if !is_git_repository(&repository.path) {
+            log::warn!(
+                "Skipping {}, which is no longer a git repository. Run `multigit doctor` to fix the config.",
+                repository.path.display()
+            );
+            continue;
+        }
+        let mut repository = repository.clone();
+        repository
+            .sources
+            .push(format!("{}repositories.{}", prefix, quote_config_key(key)));
+        repositories.push(repository);
+    }
+    let mut keys: Vec<&String> = config.directories.keys().collect();
+    keys.sort();
+    for key in keys {
+        let directory = &config.directories[key];
+        if !directory.path.is_dir() {
+            log::warn!(
+                "Skipping {}, which no longer exists. Run `multigit doctor` to fix the config.",
+                directory.path.display()
+            );
+            continue;
+        }
+        let directory_repositories = find_repositories(&directory.path)?;
+        for repository in directory_repositories {
+            let mut repository = RepositoryEntry::new(repository);
+            repository
+                .sources
+                .push(format!("{}directories.{}", prefix, quote_config_key(key)));
+            repositories.push(repository);
+        }
+    }
+    Ok(())
+}
+
 /// Finds all Git repositories within a given path.
 pub fn find_repositories(path: &Path) -> Result<Vec<PathBuf>> {
     let mut repositories = Vec::new();
@@ -740,11 +2961,462 @@ pub fn find_repositories(path: &Path) -> Result<Vec<PathBuf>> {
     Ok(repositories)
 }
 
+/// The error returned when a command is killed for running longer than its timeout.
+#[derive(Debug)]
+pub struct TimedOut {
+    /// The timeout that was exceeded.
+    pub timeout: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {}",
+            humantime::format_duration(self.timeout)
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The error returned when a git command exits unsuccessfully.
+#[derive(Debug)]
+pub struct GitFailed {
+    /// The git subcommand, e.g. `push`.
+    pub command: String,
+
+    /// The repository the command ran in.
+    pub path: PathBuf,
+
+    /// The exit code, or `None` if git was killed by a signal.
+    pub code: Option<i32>,
+}
+
+impl GitFailed {
+    /// The exit code of git's fatal errors, which include failing to reach a remote. Ordinary
+    /// failures such as a rejected push or a merge conflict exit with 1.
+    pub const FATAL: i32 = 128;
+}
+
+impl fmt::Display for GitFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Git command {} failed in repository `{}` with exit code {:?}",
+            self.command,
+            self.path.display(),
+            self.code
+        )
+    }
+}
+
+impl std::error::Error for GitFailed {}
+
+/// Returns whether a failure may go away if the operation is retried.
+fn is_transient(error: &anyhow::Error) -> bool {
+    if error.downcast_ref::<TimedOut>().is_some() {
+        return true;
+    }
+    if let Some(failed) = error.downcast_ref::<GitFailed>() {
+        return !matches!(failed.code, Some(code) if code < GitFailed::FATAL);
+    }
+    if let Some(error) = error.downcast_ref::<git2::Error>() {
+        return matches!(
+            error.class(),
+            git2::ErrorClass::Net
+                | git2::ErrorClass::Http
+                | git2::ErrorClass::Ssh
+                | git2::ErrorClass::Ssl
+                | git2::ErrorClass::Os
+        );
+    }
+    false
+}
+
+/// Runs a command to completion, killing it if it runs longer than `timeout`.
+fn run_with_timeout(
+    command: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<std::process::ExitStatus> {
+    let mut child = command.spawn()?;
+    let Some(timeout) = timeout else {
+        return Ok(child.wait()?);
+    };
+    match child.wait_timeout(timeout)? {
+        Some(status) => Ok(status),
+        None => {
+            child.kill()?;
+            child.wait()?;
+            Err(TimedOut { timeout }.into())
+        }
+    }
+}
+
+/// Fetches the default remote of a repository with libgit2.
+///
+/// Returns the number of objects and bytes received. `progress` is called as data arrives, and
+/// the fetch is aborted with `TimedOut` if it runs longer than `timeout`.
+fn fetch_repository<P>(
+    repository: &RepositoryEntry,
+    timeout: Option<Duration>,
+    mut progress: P,
+) -> Result<(usize, usize)>
+where
+    P: FnMut(&git2::Progress),
+{
+    let repo = git2::Repository::open(&repository.path)?;
+    let mut remote = tracking_remote(&repo)?;
+
+    let git_config = repo.config()?;
+    let start = Instant::now();
+    let timed_out = || timeout.is_some_and(|timeout| start.elapsed() > timeout);
+    let mut tried_ssh_agent = false;
+    let mut tried_credential_helper = false;
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(|url, username_from_url, allowed_types| {
+        if allowed_types.contains(git2::CredentialType::SSH_KEY) && !tried_ssh_agent {
+            tried_ssh_agent = true;
+            return git2::Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"));
+        }
+        if allowed_types.contains(git2::CredentialType::USER_PASS_PLAINTEXT)
+            && !tried_credential_helper
+        {
+            tried_credential_helper = true;
+            return git2::Cred::credential_helper(&git_config, url, username_from_url);
+        }
+        if allowed_types.contains(git2::CredentialType::DEFAULT) {
+            return git2::Cred::default();
+        }
+        Err(git2::Error::from_str("No usable credentials found"))
+    });
+    callbacks.transfer_progress(|stats| {
+        progress(&stats);
+        !timed_out()
+    });
+    callbacks.sideband_progress(|_| !timed_out());
+
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    fetch_options.download_tags(git2::AutotagOption::Auto);
+    let result = remote.fetch::<&str>(&[], Some(&mut fetch_options), None);
+    if let (Err(_), Some(timeout)) = (&result, timeout) {
+        if start.elapsed() >= timeout {
+            return Err(TimedOut { timeout }.into());
+        }
+    }
+    result?;
+
+    let stats = remote.stats();
+    Ok((stats.received_objects(), stats.received_bytes()))
+}
+
+/// Finds the remote the current branch tracks, falling back to `origin`.
+fn tracking_remote(repo: &git2::Repository) -> Result<git2::Remote<'_>> {
+    let tracked = repo.head().ok().and_then(|head| {
+        let refname = head.name()?.to_string();
+        let remote = repo.branch_upstream_remote(&refname).ok()?;
+        remote.as_str().map(|name| name.to_string())
+    });
+    let remote_name = tracked.unwrap_or_else(|| "origin".to_string());
+    repo.find_remote(&remote_name)
+        .with_context(|| format!("Repository has no remote named `{}`", remote_name))
+}
+
+/// Makes libgit2 give up on a connection or read that stalls for longer than `timeout`.
+///
+/// The progress callbacks only run while data arrives, so without this a remote that accepts the
+/// connection and then sends nothing would never time out. The setting is process-wide, so it
+/// must be applied before any other thread uses libgit2.
+fn set_network_timeout(timeout: Option<Duration>) -> Result<()> {
+    let Some(timeout) = timeout else {
+        return Ok(());
+    };
+    let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: fetches run on the calling thread, and no other thread touches libgit2 here.
+    unsafe {
+        git2::opts::set_server_connect_timeout_in_milliseconds(millis)?;
+        git2::opts::set_server_timeout_in_milliseconds(millis)?;
+    }
+    Ok(())
+}
+
+/// Formats a byte count for display, e.g. `1.5 MiB`.
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// A row in the summary table printed by `exec` when capturing output.
+#[derive(Tabled)]
+struct ExecResult {
+    repository: String,
+    exit_code: String,
+    duration: String,
+}
+
+/// Runs a command, printing each line of its output prefixed with `prefix`.
+///
+/// The combined output is also written to `log_path` if given.
+fn run_captured(
+    command: &mut Command,
+    prefix: &str,
+    log_path: Option<&Path>,
+) -> Result<std::process::ExitStatus> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let (sender, receiver) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let stdout_sender = sender.clone();
+    let stdout_thread = thread::spawn(move || send_lines(stdout, false, stdout_sender));
+    let stderr_thread = thread::spawn(move || send_lines(stderr, true, sender));
+
+    let mut log = String::new();
+    for (is_stderr, line) in receiver {
+        if is_stderr {
+            eprintln!("{} | {}", prefix, line);
+        } else {
+            println!("{} | {}", prefix, line);
+        }
+        log.push_str(&line);
+        log.push('\n');
+    }
+    stdout_thread.join().unwrap();
+    stderr_thread.join().unwrap();
+
+    let status = child.wait()?;
+    if let Some(log_path) = log_path {
+        fs::write(log_path, log)?;
+    }
+    Ok(status)
+}
+
+/// Sends each line read from `reader` to `sender`, replacing invalid UTF-8 rather than stopping.
+fn send_lines(reader: impl Read, is_stderr: bool, sender: mpsc::Sender<(bool, String)>) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        let _ = sender.send((is_stderr, String::from_utf8_lossy(&line).into_owned()));
+        line.clear();
+    }
+}
+
+/// Returns the log file name of each repository, numbering repositories that share a name.
+fn log_file_names(repositories: &[RepositoryEntry]) -> HashMap<PathBuf, String> {
+    let mut used = HashSet::new();
+    let mut names = HashMap::new();
+    for repository in repositories {
+        let name = repository.name();
+        let mut file_name = format!("{}.log", name);
+        let mut number = 2;
+        while !used.insert(file_name.clone()) {
+            file_name = format!("{}-{}.log", name, number);
+            number += 1;
+        }
+        names.insert(repository.path.clone(), file_name);
+    }
+    names
+}
+
+/// Expands template placeholders in each passthrough argument for a repository.
+fn expand_arguments(repository: &RepositoryEntry, arguments: &[String]) -> Result<Vec<String>> {
+    arguments
+        .iter()
+        .map(|argument| repository.expand_template(argument))
+        .collect()
+}
+
+/// Asks for a commit message in the user's editor, ignoring `#` comment lines.
+fn edit_commit_message(repositories: &[RepositoryEntry]) -> Result<String> {
+    let mut template = String::from(
+        "\n# Enter the commit message for the repositories below.\n\
+         # `{repo}` and `{branch}` are replaced with each repository's name and branch.\n\
+         # Lines starting with '#' are ignored, and an empty message aborts the commit.\n#\n",
+    );
+    for repository in repositories {
+        template.push_str(&format!("#   {}\n", repository.path.display()));
+    }
+
+    let message = edit::edit(template)?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(anyhow!("Aborting commit due to empty commit message"));
+    }
+    Ok(message.to_string())
+}
+
+/// Returns the working tree diff of a repository, or the index diff if `staged` is set.
+///
+/// The working tree diff includes untracked files, so that new files survive a `diff`/`apply`
+/// round trip.
+fn repository_diff(repo: &git2::Repository, staged: bool) -> Result<git2::Diff<'_>> {
+    let diff = if staged {
+        let head = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+        repo.diff_tree_to_index(head.as_ref(), None, None)?
+    } else {
+        let mut options = git2::DiffOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        repo.diff_index_to_workdir(None, Some(&mut options))?
+    };
+    Ok(diff)
+}
+
+/// Renders a diff in `git diff` patch format.
+fn diff_to_patch(diff: &git2::Diff) -> Result<Vec<u8>> {
+    let mut patch = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(patch)
+}
+
+/// The time and outcome of the most recent fetch of each repository.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FetchState {
+    /// Fetch records keyed by absolute repository path.
+    #[serde(default)]
+    pub repositories: HashMap<String, FetchRecord>,
+}
+
+/// The time and outcome of a single fetch.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FetchRecord {
+    /// When the fetch finished, in RFC 3339 format.
+    pub fetched: String,
+
+    /// The error message, if the fetch failed.
+    pub error: Option<String>,
+}
+
+impl FetchState {
+    /// The name of the fetch state file.
+    pub const FILE_NAME: &'static str = "fetch-state.toml";
+
+    /// Loads the fetch state from a file, or an empty state if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(FetchState::default());
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| anyhow!("Failed to parse fetch state: {}", e))
+    }
+
+    /// Saves the fetch state, replacing the file atomically so readers never see a partial write.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary_path = path.with_extension("toml.tmp");
+        fs::write(&temporary_path, toml::to_string(self)?)?;
+        fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the most recent fetch record for a repository.
+    pub fn get(&self, repository: &RepositoryEntry) -> Option<&FetchRecord> {
+        let path = repository.path.absolutize().ok()?;
+        self.repositories.get(path.to_str()?)
+    }
+
+    /// Records a fetch of a repository that finished now.
+    pub fn record(&mut self, repository: &RepositoryEntry, error: Option<String>) -> Result<()> {
+        let path = repository.path.absolutize()?;
+        let key = path.to_str().context("Failed to convert path to string")?;
+        let record = FetchRecord {
+            fetched: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            error,
+        };
+        self.repositories.insert(key.to_string(), record);
+        Ok(())
+    }
+}
+
+impl fmt::Display for FetchRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let age = humantime::parse_rfc3339(&self.fetched)
+            .ok()
+            .and_then(|fetched| SystemTime::now().duration_since(fetched).ok())
+            .map(|age| {
+                let seconds = age.as_secs();
+                let rounded = if seconds < 60 {
+                    seconds
+                } else {
+                    seconds - seconds % 60
+                };
+                humantime::format_duration(Duration::from_secs(rounded)).to_string()
+            })
+            .unwrap_or_else(|| self.fetched.clone());
+        match &self.error {
+            Some(_) => write!(f, "fetch failed {} ago", age),
+            None => write!(f, "fetched {} ago", age),
+        }
+    }
+}
+
+/// Returns the directory for state files, which is the directory containing the config file.
+pub fn state_directory_for_config(config: &InputArg) -> PathBuf {
+    let config_path = match config {
+        InputArg::Path(path) => shellexpand::tilde(path.to_str().unwrap()).to_string(),
+        InputArg::Stdin => shellexpand::tilde("~/.config/multigit/config.toml").to_string(),
+    };
+    PathBuf::from(config_path)
+        .parent()
+        .map(|parent| parent.to_path_buf())
+        .unwrap_or_default()
+}
+
 /// Checks if a path is a Git repository.
 pub fn is_git_repository(path: &Path) -> bool {
     path.join(".git").exists()
 }
 
+/// Returns a path that identifies a repository however it is reached.
+///
+/// This is the canonical path of the repository's common git directory, which is shared by all
+/// of its worktrees. Falls back to the canonical path of `path` itself.
+fn repository_identity(path: &Path) -> PathBuf {
+    let path = match git2::Repository::open(path) {
+        // A worktree's git directory names the common directory in its `commondir` file.
+        Ok(repo) => match fs::read_to_string(repo.path().join("commondir")) {
+            Ok(common_dir) if repo.is_worktree() => repo.path().join(common_dir.trim()),
+            _ => repo.path().to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    };
+    path.canonicalize().unwrap_or(path)
+}
+
 /// Checks if a path is hidden (starts with a dot).
 pub fn is_hidden(path: &Path) -> bool {
     path.file_name().unwrap().to_str().unwrap().starts_with('.')
@@ -759,10 +3431,311 @@ pub fn noneify<T>(v: &Vec<T>) -> Option<&Vec<T>> {
     }
 }
 
-#[allow(dead_code)]
-struct RepositoryError {
-    path: PathBuf,
-    error: anyhow::Error,
+/// An error that occurred while processing a single repository.
+#[derive(Debug)]
+pub struct RepositoryError {
+    /// The path to the repository.
+    pub path: PathBuf,
+
+    /// The error that occurred.
+    pub error: anyhow::Error,
+}
+
+/// The outcome of a run across repositories that did not fully succeed.
+#[derive(Debug)]
+pub enum RunError {
+    /// No repositories were selected.
+    NothingToDo,
+    /// The command failed in some or all of the processed repositories.
+    Failed {
+        /// The errors, one per failed repository.
+        errors: Vec<RepositoryError>,
+        /// The number of repositories processed before stopping.
+        processed: usize,
+        /// The number of repositories selected.
+        total: usize,
+    },
+    /// An external command exited unsuccessfully, after reporting its own errors.
+    Exited {
+        /// The command's exit code.
+        code: i32,
+    },
+}
+
+impl RunError {
+    /// Exit code when some repositories succeeded and some failed.
+    pub const PARTIAL_FAILURE: i32 = 3;
+    /// Exit code when no repository succeeded.
+    pub const TOTAL_FAILURE: i32 = 4;
+    /// Exit code when no repositories were selected.
+    pub const NOTHING_TO_DO: i32 = 5;
+
+    /// Returns the process exit code for this outcome.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::NothingToDo => Self::NOTHING_TO_DO,
+            RunError::Failed {
+                errors, processed, ..
+            } => {
+                if errors.len() == *processed {
+                    Self::TOTAL_FAILURE
+                } else {
+                    Self::PARTIAL_FAILURE
+                }
+            }
+            RunError::Exited { code } => *code,
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::NothingToDo => write!(f, "No repositories selected, nothing to do"),
+            RunError::Failed {
+                errors,
+                processed,
+                total,
+            } => {
+                write!(
+                    f,
+                    "Errors occurred in {} of {} repositories",
+                    errors.len(),
+                    total
+                )?;
+                if processed < total {
+                    write!(f, " ({} skipped)", total - processed)?;
+                }
+                let (timed_out, failed): (Vec<&RepositoryError>, Vec<&RepositoryError>) = errors
+                    .iter()
+                    .partition(|error| error.error.downcast_ref::<TimedOut>().is_some());
+                for error in failed {
+                    write!(f, "\n  {}: {}", error.path.display(), error.error)?;
+                }
+                if !timed_out.is_empty() {
+                    write!(f, "\nTimed out in {} repositories:", timed_out.len())?;
+                    for error in timed_out {
+                        write!(f, "\n  {}: {}", error.path.display(), error.error)?;
+                    }
+                }
+                Ok(())
+            }
+            RunError::Exited { code } => write!(f, "Command exited with status {}", code),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// The outcome of a command in one repository, as recorded in the last-run state.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositoryResult {
+    /// The absolute path to the repository.
+    pub path: PathBuf,
+
+    /// The error message, if the command failed in this repository.
+    pub error: Option<String>,
+}
+
+/// The outcome of running an operation in a set of repositories, as returned by
+/// `Multigit::run_repositories`.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    /// The result in each processed repository, in order.
+    pub results: Vec<RepositoryResult>,
+
+    /// The errors of the repositories that failed, in order.
+    pub errors: Vec<RepositoryError>,
+
+    /// The number of repositories selected. More than were processed if a failure stopped the run.
+    pub total: usize,
+}
+
+impl RunReport {
+    /// Returns whether the operation ran in at least one repository and succeeded in all of them.
+    pub fn is_success(&self) -> bool {
+        self.total > 0 && self.errors.is_empty()
+    }
+
+    /// Converts the report to a `RunError` unless the operation succeeded everywhere.
+    pub fn into_result(self) -> Result<()> {
+        if self.total == 0 {
+            Err(RunError::NothingToDo.into())
+        } else if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(RunError::Failed {
+                processed: self.results.len(),
+                total: self.total,
+                errors: self.errors,
+            }
+            .into())
+        }
+    }
+}
+
+/// The invocation and per-repository results of the most recent run.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LastRun {
+    /// The command-line arguments of the run, without the program name.
+    pub invocation: Vec<String>,
+
+    /// The directory the run was started from, so relative arguments resolve the same way.
+    pub working_directory: PathBuf,
+
+    /// When the run finished, in RFC 3339 format.
+    pub finished: String,
+
+    /// The result for each repository the command ran in.
+    #[serde(default)]
+    pub results: Vec<RepositoryResult>,
+}
+
+impl LastRun {
+    /// The name of the last-run state file.
+    pub const FILE_NAME: &'static str = "last-run.toml";
+
+    /// Loads the last run from a state file, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let last_run =
+            toml::from_str(&content).map_err(|e| anyhow!("Failed to parse last run: {}", e))?;
+        Ok(Some(last_run))
+    }
+
+    /// Saves the last run to a state file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the absolute paths of the repositories the command failed in.
+    pub fn failed_paths(&self) -> Vec<PathBuf> {
+        self.results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .map(|result| result.path.clone())
+            .collect()
+    }
+}
+
+/// Quotes a config key segment if it is not a bare TOML key, e.g. a repository path.
+fn quote_config_key(segment: &str) -> String {
+    if !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        segment.to_string()
+    } else {
+        format!("\"{}\"", segment)
+    }
+}
+
+/// Splits a dotted config key into its segments, honouring quoted segments.
+fn split_config_key(key: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in key.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '.' if !quoted => segments.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+/// Finds the single repository matching `name`, trying stricter kinds of match first.
+fn resolve_repository<'a>(
+    repositories: &'a [RepositoryEntry],
+    name: &str,
+) -> Result<&'a RepositoryEntry> {
+    let needle = name.to_lowercase();
+    let keys = |repository: &RepositoryEntry| {
+        let mut keys = vec![repository.name().to_lowercase()];
+        if let Some(alias) = &repository.alias {
+            keys.push(alias.to_lowercase());
+        }
+        keys
+    };
+    let matchers: [&dyn Fn(&str) -> bool; 3] = [
+        &|key| key == needle,
+        &|key| key.starts_with(&needle),
+        &|key| is_subsequence(&needle, key),
+    ];
+    for matcher in matchers {
+        let matches: Vec<&RepositoryEntry> = repositories
+            .iter()
+            .filter(|repository| keys(repository).iter().any(|key| matcher(key)))
+            .collect();
+        match matches.as_slice() {
+            [] => continue,
+            [repository] => return Ok(repository),
+            _ => {
+                let candidates: Vec<String> = matches
+                    .iter()
+                    .map(|repository| {
+                        format!("{} ({})", repository.name(), repository.path.display())
+                    })
+                    .collect();
+                return Err(anyhow!(
+                    "`{}` is ambiguous, it matches:\n  {}",
+                    name,
+                    candidates.join("\n  ")
+                ));
+            }
+        }
+    }
+    Err(anyhow!("No repository matches `{}`", name))
+}
+
+/// Returns true if the characters of `needle` appear in `haystack` in order.
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle
+        .chars()
+        .all(|c| haystack.by_ref().any(|other| other == c))
+}
+
+/// The repositories picked with `--pick`, reused with `--last`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LastSelection {
+    /// The absolute paths of the picked repositories.
+    pub paths: Vec<PathBuf>,
+}
+
+impl LastSelection {
+    /// The name of the last-selection state file.
+    pub const FILE_NAME: &'static str = "last-selection.toml";
+
+    /// Loads the last selection from a state file, if there is one.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let last_selection = toml::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse last selection: {}", e))?;
+        Ok(Some(last_selection))
+    }
+
+    /// Saves the last selection to a state file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
 }
 
 impl fmt::Display for EntryState {
@@ -784,7 +3757,7 @@ impl fmt::Display for RepositoryState {
     }
 }
 
-fn display_option(o: &Option<bool>) -> String {
+fn display_option<T: fmt::Display>(o: &Option<T>) -> String {
     match o {
         Some(s) => format!("{}", s),
         None => "".to_string(),
@@ -1,18 +1,21 @@
 //! A multi-command CLI program to manage multiple Git repositories.
 //!
 //! This program allows users to perform Git operations across multiple repositories simultaneously.
-//! It supports commands like `add`, `commit`, `push`, `pull`, `exec`, `list`, `register`, `status`, `ui`, and `unregister`.
+//! It supports commands like `add`, `apply`, `commit`, `diff`, `push`, `pull`, `exec`, `list`, `register`, `status`, `ui`, and `unregister`.
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use clap::{CommandFactory, Parser, Subcommand};
-use clap_complete::{generate, Shell};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::Shells;
+use clap_complete::CompleteEnv;
 use clap_verbosity_flag::{Verbosity, WarnLevel};
 use multigit::*;
 use patharg::InputArg;
 use shadow_rs::shadow;
 use std::io;
 use std::path::PathBuf;
-use std::time::SystemTime;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
 
 shadow!(build);
 
@@ -27,13 +30,35 @@ shadow!(build);
 struct Cli {
     /// The configuration file to use.
     #[arg(short, long)]
-    #[clap(default_value = "~/.config/multigit/config.toml")]
+    #[clap(default_value = DEFAULT_CONFIG)]
     config: InputArg,
 
     /// Directory to use instead of registering directories/repositories.
     #[arg(short, long)]
     directory: Option<PathBuf>,
 
+    /// Stop at the first repository that fails.
+    #[arg(long, global = true, conflicts_with = "keep_going")]
+    fail_fast: bool,
+
+    /// Keep going after a repository fails and report all failures at the end, even if the
+    /// config sets `fail_fast`. This is the default.
+    #[arg(long, global = true)]
+    keep_going: bool,
+
+    /// Interactively pick from the selected repositories before running the command.
+    #[arg(long, global = true, conflicts_with = "last")]
+    pick: bool,
+
+    /// Run in the repositories picked the last time `--pick` was used.
+    #[arg(long, global = true)]
+    last: bool,
+
+    /// The profile to use instead of the config's default profile.
+    #[arg(long, global = true, env = "MULTIGIT_PROFILE")]
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    profile: Option<String>,
+
     /// Set the log level.
     #[clap(flatten)]
     verbose: Verbosity<WarnLevel>,
@@ -67,6 +92,37 @@ enum Commands {
         paths: Vec<PathBuf>,
     },
 
+    /// Check that registered repositories and directories still exist.
+    ///
+    /// Suggests where missing repositories may have moved, by matching remote URLs under the
+    /// registered directories.
+    Doctor {
+        /// Remove entries that no longer exist from the config.
+        #[arg(long)]
+        prune: bool,
+    },
+
+    /// Manage repository metadata.
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommands,
+    },
+
+    /// List profiles or choose the default profile.
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+
+    /// Update the config after moving a registered repository or directory.
+    Relocate {
+        /// The registered path.
+        old: PathBuf,
+
+        /// The new path.
+        new: PathBuf,
+    },
+
     /// List registered repositories.
     ///
     /// Shows the list of repositories currently managed by the tool.
@@ -75,6 +131,15 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Repository names, aliases or unique prefixes to select, like `--repo`.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        names: Vec<String>,
+
         #[arg(short, long)]
         #[clap(default_value = "false")]
         detailed: bool,
@@ -86,7 +151,18 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
         /// Additional arguments to pass through to the `git add` command.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         passthrough: Vec<String>,
     },
     /// Commit changes in the selected repositories.
@@ -95,7 +171,32 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
+        /// The commit message. `{repo}` and `{branch}` are replaced per repository.
+        ///
+        /// Opens `$EDITOR` once for all repositories if not provided.
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// A trailer to append to every commit message, e.g. `Reviewed-by: Jane Doe`.
+        #[arg(long)]
+        trailer: Vec<String>,
+
+        /// Append a shared `Change-Set: <uuid>` trailer linking the commits.
+        #[arg(long)]
+        change_set: bool,
+
         /// Additional arguments to pass through to the `git commit` command.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
         passthrough: Vec<String>,
     },
@@ -105,8 +206,29 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
+        /// Kill the command in a repository if it runs longer than this, e.g. `30s` or `2m`.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+
+        /// Retry the command this many times, with backoff, after a timeout or a failure that a
+        /// retry may fix, such as an unreachable remote.
+        #[arg(long)]
+        retries: Option<u32>,
+
         /// Additional arguments to pass through to the `git push` command.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        #[arg(add = ArgValueCandidates::new(branch_candidates))]
         passthrough: Vec<String>,
     },
     /// Fetch changes from remote repositories.
@@ -115,8 +237,33 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
+        /// Fetch with libgit2 and show progress instead of running `git fetch`.
+        #[arg(long, conflicts_with = "passthrough")]
+        native: bool,
+
+        /// Kill the command in a repository if it runs longer than this, e.g. `30s` or `2m`.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+
+        /// Retry the command this many times, with backoff, after a timeout or a failure that a
+        /// retry may fix, such as an unreachable remote.
+        #[arg(long)]
+        retries: Option<u32>,
+
         /// Additional arguments to pass through to the `git fetch` command.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        #[arg(add = ArgValueCandidates::new(branch_candidates))]
         passthrough: Vec<String>,
     },
 
@@ -126,49 +273,312 @@ enum Commands {
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
+        /// Kill the command in a repository if it runs longer than this, e.g. `30s` or `2m`.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+
+        /// Retry the command this many times, with backoff, after a timeout or a failure that a
+        /// retry may fix, such as an unreachable remote.
+        #[arg(long)]
+        retries: Option<u32>,
+
         /// Additional arguments to pass through to the `git pull` command.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        #[arg(add = ArgValueCandidates::new(branch_candidates))]
         passthrough: Vec<String>,
     },
     /// Execute a custom command in the selected repositories.
+    ///
+    /// `{path}`, `{name}`, `{branch}`, `{remote_url}`, `{upstream}`, `{alias}`, `{description}`,
+    /// `{owner}`, `{default_branch}` and `{meta.KEY}` are expanded per repository.
     Exec {
         /// Filters to select specific repositories.
         #[arg(short, long)]
         filter: Vec<Filter>,
 
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Ignore the command's default filters and run on all repositories.
+        #[arg(long)]
+        all: bool,
+
+        /// Run the command through `sh -c`, so pipes and redirections work.
+        #[arg(long)]
+        shell: bool,
+
+        /// Prefix each output line with the repository name and print a summary table.
+        #[arg(long)]
+        capture: bool,
+
+        /// Write each repository's full output to `<LOG_DIR>/<repository>.log`. Implies `--capture`.
+        ///
+        /// Repositories that share a name are numbered, e.g. `<repository>-2.log`.
+        #[arg(long)]
+        log_dir: Option<PathBuf>,
+
         /// The command to execute.
+        ///
+        /// Words before a `--` are repository names to select instead, like `--repo`.
         #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
         command: Vec<String>,
     },
+    /// Show the combined diff of all dirty repositories.
+    Diff {
+        /// Filters to select specific repositories.
+        #[arg(short, long)]
+        filter: Vec<Filter>,
+
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Repository names, aliases or unique prefixes to select, like `--repo`.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        names: Vec<String>,
+
+        /// Show changes staged in the index instead of the working tree.
+        #[arg(long)]
+        staged: bool,
+
+        /// Show a diffstat instead of the full patch.
+        #[arg(long)]
+        stat: bool,
+
+        /// Write one `<repository>.patch` file per repository into this directory.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Apply patch files written by `diff --output` to the matching repositories.
+    Apply {
+        /// Filters to select specific repositories.
+        #[arg(short, long)]
+        filter: Vec<Filter>,
+
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Apply the patches to the index as well as the working tree.
+        #[arg(long)]
+        staged: bool,
+
+        /// Directory containing the patch files.
+        patches: PathBuf,
+    },
     /// Show the status of repositories.
     Status {
         /// Filters to select specific repositories.
         #[arg(short, long)]
         filter: Vec<Filter>,
+
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Repository names, aliases or unique prefixes to select, like `--repo`.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        names: Vec<String>,
     },
     /// Open the configured git UI program for the selected repositories.
     UI {
         /// Filters to select specific repositories.
         #[arg(short, long)]
         filter: Vec<Filter>,
+
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Repository names, aliases or unique prefixes to select, like `--repo`.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        names: Vec<String>,
+    },
+    /// Show an interactive dashboard of repositories.
+    Tui {
+        /// Filters to select specific repositories.
+        #[arg(short, long)]
+        filter: Vec<Filter>,
+
+        /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+        #[arg(short = 'r', long = "repo", value_name = "NAME")]
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repositories: Vec<String>,
+
+        /// Repository names, aliases or unique prefixes to select, like `--repo`.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        names: Vec<String>,
+    },
+    /// Show the per-repository results of the previous run.
+    Last {},
+    /// Repeat the previous run's command.
+    Rerun {
+        /// Only rerun in the repositories where the previous run failed.
+        #[arg(long)]
+        failed: bool,
+    },
+    /// Fetch all registered repositories in the background on a schedule.
+    ///
+    /// The last fetch time of each repository is shown by `list --detailed` and `status`.
+    Daemon {
+        /// How long to wait between fetches, e.g. `15m`. Defaults to the `[daemon]` config or 15 minutes.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        interval: Option<Duration>,
+
+        /// Run in the foreground instead of detaching, e.g. under a process supervisor.
+        #[arg(long)]
+        foreground: bool,
+
+        /// Fetch once in the foreground and exit.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Print the path of a repository.
+    Path {
+        /// A repository name, alias or unique prefix.
+        #[arg(required_unless_present = "list", add = ArgValueCandidates::new(repository_candidates))]
+        name: Option<String>,
+
+        /// List the names and aliases of all repositories instead.
+        #[arg(long, conflicts_with = "name")]
+        list: bool,
+    },
+    /// Print a script that defines the `mg` shell function, e.g. `eval "$(multigit shell-init zsh)"`.
+    ///
+    /// `mg cd <name>` changes to a repository's directory. Other arguments are passed to multigit.
+    ShellInit {
+        /// The shell to generate the script for.
+        shell: InitShell,
     },
     /// Edit the configuration file.
-    Config {},
-    /// Generate shell completions.
+    Config {
+        /// Print the value at this dotted key instead, e.g. `daemon.interval`.
+        #[arg(add = ArgValueCandidates::new(config_key_candidates))]
+        key: Option<String>,
+    },
+    /// Print a script that registers shell completions, e.g. `source <(multigit completions -s bash)`.
+    ///
+    /// Repository names and aliases, config keys, profile names and branch names common to all
+    /// repositories are completed by calling back into multigit, so they are always current.
     Completions {
+        /// The shell to generate the script for: bash, elvish, fish, powershell or zsh.
         #[arg(short, long)]
         shell: String,
     },
+
+    /// A command alias defined under `[aliases]` in the config file, or else an external
+    /// `multigit-<name>` command on PATH.
+    #[command(external_subcommand)]
+    External(Vec<String>),
+}
+
+/// Run an external `multigit-<name>` command on the selected repositories.
+///
+/// Other arguments are passed on to the command. Put them after `--` if they could be taken for
+/// multigit's own options.
+#[derive(Parser, Debug)]
+struct PluginArgs {
+    /// Filters to select specific repositories.
+    #[arg(short, long)]
+    filter: Vec<Filter>,
+
+    /// Ignore the command's default filters and select all repositories.
+    #[arg(long)]
+    all: bool,
+
+    /// A repository name, alias or unique prefix to select, instead of filters. Repeatable.
+    #[arg(short = 'r', long = "repo", value_name = "NAME")]
+    repositories: Vec<String>,
+
+    /// Interactively pick from the selected repositories before running the command.
+    #[arg(long, conflicts_with = "last")]
+    pick: bool,
+
+    /// Run in the repositories picked the last time `--pick` was used.
+    #[arg(long)]
+    last: bool,
+
+    /// The profile to use instead of the config's default profile.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Arguments to pass to the plugin.
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    arguments: Vec<String>,
+}
+
+/// Subcommands of `multigit repo`.
+#[derive(Subcommand, Debug)]
+enum RepoCommands {
+    /// Set metadata on a repository.
+    ///
+    /// `alias`, `description`, `owner` and `default_branch` are built in. Any other key is
+    /// stored as custom metadata. Metadata is shown by `list --detailed`, can be matched with
+    /// `--filter KEY=VALUE` and is expanded in `exec` templates as `{owner}` or `{meta.KEY}`.
+    Set {
+        /// A repository name, alias or unique prefix.
+        #[arg(add = ArgValueCandidates::new(repository_candidates))]
+        repository: String,
+
+        /// `KEY=VALUE` pairs to set. An empty value removes the key.
+        #[arg(required = true, add = ArgValueCandidates::new(metadata_candidates))]
+        values: Vec<String>,
+    },
+}
+
+/// Subcommands of `multigit profile`.
+#[derive(Subcommand, Debug)]
+enum ProfileCommands {
+    /// List the profiles, marking the one in use.
+    List,
+
+    /// Use a profile when neither `--profile` nor `MULTIGIT_PROFILE` is given.
+    Use {
+        /// The profile name, or `default` for the top-level repositories and directories.
+        #[arg(add = ArgValueCandidates::new(profile_candidates))]
+        name: String,
+    },
 }
 
 /// The main entry point of the program.
 fn main() -> Result<()> {
+    // Answer completion requests from the script printed by `completions`.
+    CompleteEnv::with_factory(Cli::command).complete();
+
     better_panic::install();
 
     let start_time = SystemTime::now();
 
     // Parse command-line arguments into the `Cli` struct.
-    let args = Cli::parse();
+    let argv: Vec<String> = std::env::args().collect();
+    let args = Cli::parse_from(&argv);
+    // Clap only checks `conflicts_with` among global arguments given on the same side of the
+    // subcommand, so catch `--fail-fast push --keep-going` here.
+    if args.fail_fast && args.keep_going {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the argument '--fail-fast' cannot be used with '--keep-going'",
+            )
+            .exit();
+    }
 
     setup_logger(
         args.verbose.log_level_filter(),
@@ -176,55 +586,621 @@ fn main() -> Result<()> {
         start_time,
     )?;
 
+    let (args, argv) = expand_aliases(args, argv)?;
+    let invocation = argv.into_iter().skip(1).collect();
+    if let Err(error) = run(args, invocation, None) {
+        if let Some(run_error) = error.downcast_ref::<RunError>() {
+            if !matches!(run_error, RunError::Exited { .. }) {
+                eprintln!("Error: {}", run_error);
+            }
+            std::process::exit(run_error.exit_code());
+        }
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Expands a command alias from the config file, like git does, and parses the result again.
+///
+/// An alias is replaced by its definition. Arguments given after it are inserted before the
+/// definition's `--`, and any after their own `--` are appended. A definition starting with `!`
+/// is a shell command run with `exec --shell`. Aliases can't replace built-in commands. Returns
+/// the parsed arguments and the expanded argv.
+fn expand_aliases(mut args: Cli, mut argv: Vec<String>) -> Result<(Cli, Vec<String>)> {
+    let mut expanded = Vec::new();
+    while let Commands::External(external) = &args.command {
+        // Reading the config from stdin here would leave nothing for `run`.
+        if matches!(args.config, InputArg::Stdin) {
+            break;
+        }
+        let name = &external[0];
+        let config = Config::load_read_only(args.config.clone())?
+            .select_profile(args.profile.as_deref(), false)?;
+        let Some(definition) = config.alias(name) else {
+            break;
+        };
+        if expanded.contains(name) {
+            return Err(anyhow!("Alias `{}` refers to itself", name));
+        }
+        expanded.push(name.clone());
+
+        let mut expansion = match definition.strip_prefix('!') {
+            Some(command) => vec![
+                "exec".to_string(),
+                "--shell".to_string(),
+                "--".to_string(),
+                command.to_string(),
+            ],
+            None => shell_words::split(definition)
+                .with_context(|| format!("Invalid alias `{}`", name))?,
+        };
+        // Clap drops `--` from the arguments of an external subcommand, so take them from argv.
+        let start = external_start(&argv, external);
+        let arguments = argv[start + 1..].to_vec();
+        let (options, trailing) = match arguments.iter().position(|word| word == "--") {
+            Some(index) => (&arguments[..index], &arguments[index + 1..]),
+            None => (&arguments[..], &[][..]),
+        };
+        match expansion.iter().position(|word| word == "--") {
+            Some(index) => {
+                expansion.splice(index..index, options.iter().cloned());
+            }
+            None => {
+                expansion.extend(options.iter().cloned());
+                if !trailing.is_empty() {
+                    expansion.push("--".to_string());
+                }
+            }
+        }
+        expansion.extend(trailing.iter().cloned());
+
+        argv.truncate(start);
+        argv.extend(expansion);
+        log::debug!("Expanded alias `{}` to {:?}", name, argv);
+        args = Cli::parse_from(&argv);
+    }
+    Ok((args, argv))
+}
+
+/// Returns the index in `argv` of the external subcommand whose arguments clap parsed as
+/// `external`, by matching them from the end and skipping the `--` separators clap removed.
+fn external_start(argv: &[String], external: &[String]) -> usize {
+    let mut index = argv.len();
+    let mut remaining = external.len();
+    while remaining > 0 && index > 0 {
+        index -= 1;
+        if argv[index] == external[remaining - 1] {
+            remaining -= 1;
+        }
+    }
+    index
+}
+
+impl Commands {
+    /// Returns the repository names given on the command line, if the command accepts any.
+    fn repository_names(&self) -> Vec<String> {
+        match self {
+            Commands::List {
+                repositories,
+                names,
+                ..
+            }
+            | Commands::Diff {
+                repositories,
+                names,
+                ..
+            }
+            | Commands::Status {
+                repositories,
+                names,
+                ..
+            }
+            | Commands::UI {
+                repositories,
+                names,
+                ..
+            }
+            | Commands::Tui {
+                repositories,
+                names,
+                ..
+            } => repositories.iter().chain(names).cloned().collect(),
+            Commands::Add { repositories, .. }
+            | Commands::Commit { repositories, .. }
+            | Commands::Push { repositories, .. }
+            | Commands::Fetch { repositories, .. }
+            | Commands::Pull { repositories, .. }
+            | Commands::Exec { repositories, .. }
+            | Commands::Apply { repositories, .. } => repositories.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Moves the words before the first `--` of `invocation` out of the passthrough arguments of
+    /// a git or `exec` command and into its repository names.
+    ///
+    /// Without a `--`, all the words stay passthrough arguments, so `multigit add .` and
+    /// `multigit pull origin main` keep their meaning.
+    fn split_repository_names(&mut self, invocation: &[String]) {
+        let (repositories, passthrough) = match self {
+            Commands::Add {
+                repositories,
+                passthrough,
+                ..
+            }
+            | Commands::Commit {
+                repositories,
+                passthrough,
+                ..
+            }
+            | Commands::Push {
+                repositories,
+                passthrough,
+                ..
+            }
+            | Commands::Fetch {
+                repositories,
+                passthrough,
+                ..
+            }
+            | Commands::Pull {
+                repositories,
+                passthrough,
+                ..
+            }
+            | Commands::Exec {
+                repositories,
+                command: passthrough,
+                ..
+            } => (repositories, passthrough),
+            _ => return,
+        };
+        let Some(separator) = invocation.iter().position(|word| word == "--") else {
+            return;
+        };
+        // Everything after the first `--` is passed through. Clap keeps that `--` among the
+        // values when words come before it, except for `add`, whose values aren't trailing.
+        let trailing = &invocation[separator + 1..];
+        if !passthrough.ends_with(trailing) {
+            return;
+        }
+        let mut names = passthrough[..passthrough.len() - trailing.len()].to_vec();
+        if names.last().is_some_and(|word| word == "--") {
+            names.pop();
+        }
+        // Options before the `--`, as in `commit --amend -- file`, belong to git.
+        if names.iter().any(|word| word.starts_with('-')) {
+            return;
+        }
+        passthrough.drain(..passthrough.len() - trailing.len());
+        repositories.extend(names);
+    }
+}
+
+/// Narrows an optional restriction to the given paths.
+fn restrict(only: Option<Vec<PathBuf>>, paths: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+    match only {
+        Some(only) => Some(
+            only.into_iter()
+                .filter(|path| paths.contains(path))
+                .collect(),
+        ),
+        None => Some(paths),
+    }
+}
+
+/// Runs a parsed command line, optionally restricted to the repositories at `only`.
+fn run(args: Cli, invocation: Vec<String>, only: Option<Vec<PathBuf>>) -> Result<()> {
+    let mut args = args;
+    args.command.split_repository_names(&invocation);
+    // The selection options of an external command can also follow its name, so merge them in
+    // before the profile and the last selection are looked up.
+    let plugin_args = match &args.command {
+        Commands::External(external) => {
+            let start = external_start(&invocation, external);
+            let plugin_args = PluginArgs::parse_from(
+                std::iter::once(format!("{} {}", build::PROJECT_NAME, external[0]))
+                    .chain(invocation[start + 1..].iter().cloned()),
+            );
+            args.pick |= plugin_args.pick;
+            args.last |= plugin_args.last;
+            if args.pick && args.last {
+                return Err(anyhow!("`--pick` and `--last` can't be used together"));
+            }
+            if plugin_args.profile.is_some() {
+                args.profile = plugin_args.profile.clone();
+            }
+            Some(plugin_args)
+        }
+        _ => None,
+    };
     log::debug!("{:?}", args);
 
-    let config = Config::load(args.config)?;
+    let state_directory = state_directory_for_config(&args.config);
+    // Registering into a profile that doesn't exist yet creates it.
+    let create_profile = matches!(args.command, Commands::Register { .. });
+    let mut config =
+        Config::load(args.config)?.select_profile(args.profile.as_deref(), create_profile)?;
+    // Project configs apply to `--directory` if given, or else to the working directory.
+    match &args.directory {
+        Some(directory) => config.load_layers(directory)?,
+        None => config.load_layers(&std::env::current_dir()?)?,
+    }
 
     // Create a new instance of `Multigit`.
     let mut multigit = Multigit::new(config, args.directory).unwrap();
+    multigit.invocation = invocation;
+    multigit.state_directory = Some(state_directory.clone());
+    let mut only = only;
+    if args.last {
+        let last_selection_path = multigit
+            .profile_state_path(LastSelection::FILE_NAME)
+            .context("No state directory configured")?;
+        let last_selection = LastSelection::load(&last_selection_path)?
+            .context("No previous selection recorded, use --pick first")?;
+        only = restrict(only, last_selection.paths);
+    }
+    let names = args.command.repository_names();
+    if !names.is_empty() {
+        only = restrict(only, multigit.resolve_repositories(&names)?);
+        multigit.named = true;
+    }
+    multigit.only = only;
+    multigit.fail_fast = args.fail_fast || (multigit.config.fail_fast() && !args.keep_going);
+    multigit.pick = args.pick;
 
     // Match the provided command and execute the corresponding action.
     match &args.command {
-        Commands::List { filter, detailed } => multigit.list(noneify(filter), detailed),
+        Commands::List {
+            filter, detailed, ..
+        } => multigit.list(noneify(filter), detailed),
         Commands::Register { paths } => multigit.register(paths),
-        Commands::Status { filter } => multigit.status(noneify(filter)),
+        Commands::Status { filter, .. } => multigit.status(noneify(filter)),
         Commands::Unregister { paths, all } => multigit.unregister(paths, all),
-        Commands::UI { filter } => multigit.ui(noneify(filter)),
-        Commands::Exec { filter, command } => multigit.exec(noneify(filter), command),
+        Commands::Doctor { prune } => multigit.doctor(prune),
+        Commands::Relocate { old, new } => multigit.relocate(old, new),
+        Commands::Repo { command } => match command {
+            RepoCommands::Set { repository, values } => multigit.repo_set(repository, values),
+        },
+        Commands::Profile { command } => match command {
+            ProfileCommands::List => multigit.profile_list(),
+            ProfileCommands::Use { name } => multigit.profile_use(name),
+        },
+        Commands::UI { filter, .. } => multigit.ui(noneify(filter)),
+        Commands::Tui { filter, .. } => multigit.tui(noneify(filter)),
+        Commands::Exec {
+            filter,
+            all,
+            shell,
+            capture,
+            log_dir,
+            command,
+            ..
+        } => multigit.exec(noneify(filter), all, shell, capture, log_dir, command),
         Commands::Add {
             filter,
+            all,
             passthrough,
-        } => multigit.add(noneify(filter), passthrough),
+            ..
+        } => multigit.add(noneify(filter), all, passthrough),
         Commands::Commit {
             filter,
+            all,
+            message,
+            trailer,
+            change_set,
+            passthrough,
+            ..
+        } => multigit.commit(
+            noneify(filter),
+            all,
+            message,
+            trailer,
+            change_set,
             passthrough,
-        } => multigit.commit(noneify(filter), passthrough),
+        ),
         Commands::Push {
             filter,
+            all,
+            timeout,
+            retries,
             passthrough,
-        } => multigit.push(noneify(filter), passthrough),
+            ..
+        } => multigit.push(noneify(filter), all, timeout, retries, passthrough),
         Commands::Pull {
             filter,
+            all,
+            timeout,
+            retries,
             passthrough,
-        } => multigit.pull(noneify(filter), passthrough),
+            ..
+        } => multigit.pull(noneify(filter), all, timeout, retries, passthrough),
         Commands::Fetch {
             filter,
+            all,
+            native,
+            timeout,
+            retries,
             passthrough,
-        } => multigit.fetch(noneify(filter), passthrough),
-        Commands::Config {} => multigit.config(),
+            ..
+        } => {
+            if *native {
+                multigit.fetch_native(noneify(filter), all, timeout, retries)
+            } else {
+                multigit.fetch(noneify(filter), all, timeout, retries, passthrough)
+            }
+        }
+        Commands::Diff {
+            filter,
+            staged,
+            stat,
+            output,
+            ..
+        } => multigit.diff(noneify(filter), staged, stat, output),
+        Commands::Apply {
+            filter,
+            staged,
+            patches,
+            ..
+        } => multigit.apply(noneify(filter), patches, staged),
+        Commands::Last {} => multigit.last(),
+        Commands::Rerun { failed } => {
+            let last_run_path = multigit
+                .profile_state_path(LastRun::FILE_NAME)
+                .context("No state directory configured")?;
+            let last_run = LastRun::load(&last_run_path)?.context("No previous run recorded")?;
+            let only = if *failed {
+                let failed_paths = last_run.failed_paths();
+                if failed_paths.is_empty() {
+                    println!("No failed repositories to rerun.");
+                    return Ok(());
+                }
+                Some(failed_paths)
+            } else {
+                None
+            };
+            std::env::set_current_dir(&last_run.working_directory)?;
+            let args = Cli::try_parse_from(
+                std::iter::once(build::PROJECT_NAME.to_string())
+                    .chain(last_run.invocation.iter().cloned()),
+            )?;
+            run(args, last_run.invocation, only)
+        }
+        Commands::Daemon {
+            interval,
+            foreground,
+            once,
+        } => {
+            if *foreground || *once {
+                multigit.daemon(interval, once)
+            } else {
+                spawn_daemon(&multigit)
+            }
+        }
+        Commands::Config { key } => multigit.config(key.as_ref()),
+        Commands::Path { name, list } => multigit.path(name.as_ref(), list),
+        Commands::ShellInit { shell } => {
+            print!("{}", shell_init(*shell, build::PROJECT_NAME));
+            Ok(())
+        }
+        Commands::External(external) => {
+            let name = &external[0];
+            let plugin_args = plugin_args.context("External command arguments weren't parsed")?;
+            if !plugin_args.repositories.is_empty() {
+                let paths = multigit.resolve_repositories(&plugin_args.repositories)?;
+                multigit.only = restrict(multigit.only.take(), paths);
+                multigit.named = true;
+            }
+            let status = multigit.plugin(
+                name,
+                noneify(&plugin_args.filter),
+                &plugin_args.all,
+                &plugin_args.arguments,
+            )?;
+            if !status.success() {
+                return Err(RunError::Exited {
+                    code: status.code().unwrap_or(1),
+                }
+                .into());
+            }
+            Ok(())
+        }
         Commands::Completions { shell } => {
-            let shell: Shell = shell.parse().unwrap_or(Shell::Bash);
-            let mut cmd = Cli::command();
-            generate(shell, &mut cmd, "myapp", &mut io::stdout());
+            let shells = Shells::builtins();
+            let completer = shells.completer(shell).ok_or_else(|| {
+                anyhow!(
+                    "Unsupported shell `{}`, expected one of: {}",
+                    shell,
+                    shells.names().collect::<Vec<_>>().join(", ")
+                )
+            })?;
+            completer.write_registration(
+                "COMPLETE",
+                build::PROJECT_NAME,
+                build::PROJECT_NAME,
+                build::PROJECT_NAME,
+                &mut io::stdout(),
+            )?;
             Ok(())
         }
     }
 }
 
+/// Loads the default configuration for completing argument values.
+///
+/// Completion runs without the rest of the command line, so `--config` and `--profile` are not
+/// taken into account. `MULTIGIT_PROFILE` is.
+fn completion_multigit() -> Option<Multigit> {
+    let profile = std::env::var("MULTIGIT_PROFILE").ok();
+    let mut config = Config::load_read_only(InputArg::Path(PathBuf::from(DEFAULT_CONFIG)))
+        .ok()?
+        .select_profile(profile.as_deref(), false)
+        .ok()?;
+    config.load_layers(&std::env::current_dir().ok()?).ok()?;
+    Multigit::new(config, None).ok()
+}
+
+/// Completes repository names and aliases.
+fn repository_candidates() -> Vec<CompletionCandidate> {
+    let Some(multigit) = completion_multigit() else {
+        return Vec::new();
+    };
+    multigit
+        .repository_names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, path)| {
+            CompletionCandidate::new(name).help(Some(path.display().to_string().into()))
+        })
+        .collect()
+}
+
+/// Completes configuration keys.
+fn config_key_candidates() -> Vec<CompletionCandidate> {
+    let Some(multigit) = completion_multigit() else {
+        return Vec::new();
+    };
+    multigit
+        .config_keys()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Completes branch names that all repositories have.
+fn branch_candidates() -> Vec<CompletionCandidate> {
+    let Some(multigit) = completion_multigit() else {
+        return Vec::new();
+    };
+    multigit
+        .common_branches()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Completes `KEY=` for the built-in metadata fields, and `default_branch=` with each branch
+/// name that all repositories have.
+fn metadata_candidates() -> Vec<CompletionCandidate> {
+    let mut candidates = METADATA_FIELDS
+        .iter()
+        .map(|field| CompletionCandidate::new(format!("{}=", field)))
+        .collect::<Vec<_>>();
+    candidates.extend(
+        branch_candidates()
+            .into_iter()
+            .map(|branch| branch.add_prefix("default_branch=")),
+    );
+    candidates
+}
+
+/// Completes profile names.
+fn profile_candidates() -> Vec<CompletionCandidate> {
+    let Some(multigit) = completion_multigit() else {
+        return Vec::new();
+    };
+    multigit
+        .config
+        .profile_names()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Starts the daemon in the background by running this command again with `--foreground`.
+fn spawn_daemon(multigit: &Multigit) -> Result<()> {
+    // Check the lock here, where the user sees the error, rather than in the detached child. It's
+    // released again before spawning so the child can take it.
+    drop(multigit.lock_daemon()?);
+    let log_path = multigit
+        .profile_state_path(DAEMON_LOG_FILE_NAME)
+        .context("No state directory configured")?;
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .arg("--foreground")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let child = command.spawn()?;
+    println!(
+        "Started multigit daemon (pid {}), logging to {}",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod common;
+
 #[cfg(test)]
 mod tests {
     //use super::*;
+    use crate::common::{self, commit};
     use assert_cmd::Command;
+    use multigit::{LastRun, LastSelection};
+    use std::path::{Path, PathBuf};
+
+    /// A temporary directory for one test, removed when it is dropped even if the test fails.
+    struct Workspace(tempfile::TempDir);
+
+    impl Workspace {
+        fn new() -> Self {
+            Workspace(common::tempdir())
+        }
+
+        fn path(&self) -> &Path {
+            self.0.path()
+        }
+
+        fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+            self.path().join(path)
+        }
+
+        fn config(&self) -> PathBuf {
+            self.join("config.toml")
+        }
+
+        /// Creates an empty repository at `path`.
+        fn init(&self, path: impl AsRef<Path>) -> git2::Repository {
+            git2::Repository::init(self.join(path)).unwrap()
+        }
+
+        /// Returns a `multigit` command using the workspace's config file.
+        fn multigit(&self) -> Command {
+            let mut cmd = Command::cargo_bin("multigit").unwrap();
+            cmd.arg("--config").arg(self.config());
+            cmd.env_remove("MULTIGIT_PROFILE");
+            cmd
+        }
+
+        /// Returns a `multigit` command that runs in the repositories under `directory`.
+        fn multigit_in(&self, directory: impl AsRef<Path>) -> Command {
+            let mut cmd = self.multigit();
+            cmd.arg("--directory").arg(self.join(directory));
+            cmd
+        }
+    }
+
+    /// Returns the standard output of a successful command.
+    fn stdout(cmd: &mut Command) -> String {
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).unwrap()
+    }
 
     #[test]
     fn run_empty() {
@@ -239,4 +1215,1008 @@ mod tests {
         cmd.args(["--help"]);
         cmd.assert().success();
     }
+
+    #[test]
+    fn run_diff_and_apply() {
+        let workspace = Workspace::new();
+        let repo = workspace.init("example");
+        let tracked = workspace.join("example").join("tracked.txt");
+        let untracked = workspace.join("example").join("untracked.txt");
+        std::fs::write(&tracked, "one\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        index.write().unwrap();
+        commit(&repo, "First");
+
+        std::fs::write(&tracked, "two\n").unwrap();
+        std::fs::write(&untracked, "new\n").unwrap();
+        let patches = workspace.join("patches");
+        workspace
+            .multigit_in(workspace.path())
+            .args(["diff", "--output"])
+            .arg(&patches)
+            .assert()
+            .success();
+
+        std::fs::write(&tracked, "one\n").unwrap();
+        std::fs::remove_file(&untracked).unwrap();
+        workspace
+            .multigit_in(workspace.path())
+            .arg("apply")
+            .arg(&patches)
+            .assert()
+            .success();
+        assert_eq!(std::fs::read_to_string(&tracked).unwrap(), "two\n");
+        assert_eq!(std::fs::read_to_string(&untracked).unwrap(), "new\n");
+
+        workspace.init(Path::new("copy").join("example"));
+        std::fs::write(workspace.join("copy").join("example").join("file.txt"), "").unwrap();
+        workspace
+            .multigit_in(workspace.path())
+            .args(["diff", "--output"])
+            .arg(workspace.join("clashing"))
+            .assert()
+            .failure();
+        assert!(!workspace.join("clashing").exists());
+    }
+
+    #[test]
+    fn run_commit_staged_changes() {
+        let workspace = Workspace::new();
+        let staged = workspace.init("staged");
+        std::fs::write(workspace.join("staged").join("file.txt"), "staged\n").unwrap();
+        let mut index = staged.index().unwrap();
+        index.add_path(Path::new("file.txt")).unwrap();
+        index.write().unwrap();
+        let untracked = workspace.init("untracked");
+        std::fs::write(workspace.join("untracked").join("file.txt"), "untracked\n").unwrap();
+
+        // Both repositories are on a branch without commits yet.
+        workspace
+            .multigit_in(workspace.path())
+            .args(["commit", "-m", "Commit {repo} on {branch}"])
+            .env("GIT_AUTHOR_NAME", "Multigit")
+            .env("GIT_AUTHOR_EMAIL", "multigit@example.com")
+            .env("GIT_COMMITTER_NAME", "Multigit")
+            .env("GIT_COMMITTER_EMAIL", "multigit@example.com")
+            .assert()
+            .success();
+
+        let head = staged.head().unwrap();
+        let message = head
+            .peel_to_commit()
+            .unwrap()
+            .message()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            message.trim_end(),
+            format!("Commit staged on {}", head.shorthand().unwrap())
+        );
+        assert!(untracked.head().is_err());
+    }
+
+    #[test]
+    fn run_pull_tracking_repositories() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        git2::Repository::clone(
+            workspace.join("upstream").to_str().unwrap(),
+            workspace.join("workspace").join("clone"),
+        )
+        .unwrap();
+        let local = workspace.init(Path::new("workspace").join("local"));
+        commit(&local, "First");
+        let head = commit(&upstream, "Second");
+        for name in ["clone", "local"] {
+            std::fs::write(workspace.join("workspace").join(name).join("new.txt"), "").unwrap();
+        }
+
+        // `local` is dirty too, but has no upstream to pull from.
+        workspace
+            .multigit_in("workspace")
+            .args(["pull", "--filter", "dirty"])
+            .assert()
+            .success();
+        let clone = git2::Repository::open(workspace.join("workspace").join("clone")).unwrap();
+        assert_eq!(clone.head().unwrap().target(), Some(head));
+    }
+
+    #[test]
+    fn run_exec_with_placeholders() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+
+        let output = stdout(workspace.multigit_in(workspace.path()).args([
+            "exec", "--shell", "--", "echo", "{name}", "|", "tr", "a-z", "A-Z",
+        ]));
+        assert_eq!(output, "EXAMPLE\n");
+
+        let output = stdout(workspace.multigit_in(workspace.path()).args([
+            "exec",
+            "--",
+            "echo",
+            "@{upstream}..HEAD",
+            "{{name}",
+            "{{{name}}",
+        ]));
+        assert_eq!(output, "@{upstream}..HEAD {name} {example}\n");
+    }
+
+    #[test]
+    fn run_exec_with_log_dir() {
+        let workspace = Workspace::new();
+        workspace.init(Path::new("first").join("example"));
+        workspace.init(Path::new("second").join("example"));
+
+        let logs = workspace.join("logs");
+        workspace
+            .multigit_in(workspace.path())
+            .args(["exec", "--shell", "--log-dir"])
+            .arg(&logs)
+            .args(["--", "printf 'one\\n\\377\\ntwo\\n'"])
+            .assert()
+            .success();
+        for name in ["example.log", "example-2.log"] {
+            let log = std::fs::read_to_string(logs.join(name)).unwrap();
+            assert_eq!(log, "one\n\u{FFFD}\ntwo\n");
+        }
+    }
+
+    #[test]
+    fn run_last_and_rerun() {
+        let workspace = Workspace::new();
+        for name in ["first", "second"] {
+            commit(&workspace.init(name), "First");
+        }
+        std::fs::write(workspace.join("second").join("fail"), "").unwrap();
+
+        let exec = workspace
+            .multigit_in(workspace.path())
+            .args(["exec", "--shell", "--", "echo {name}; test ! -e fail"])
+            .assert()
+            .failure()
+            .get_output()
+            .stdout
+            .clone();
+        assert_eq!(String::from_utf8(exec).unwrap(), "first\nsecond\n");
+
+        // Read-only commands don't replace the recorded run.
+        workspace
+            .multigit_in(workspace.path())
+            .arg("status")
+            .assert()
+            .success();
+        let last = stdout(workspace.multigit().arg("last"));
+        assert!(last.contains("exec --shell"));
+        assert!(last.contains("failed with exit code"));
+
+        std::fs::remove_file(workspace.join("second").join("fail")).unwrap();
+        let rerun = stdout(workspace.multigit().args(["rerun", "--failed"]));
+        assert_eq!(rerun, "second\n");
+        assert!(!stdout(workspace.multigit().arg("last")).contains("failed with exit code"));
+    }
+
+    #[test]
+    fn run_exec_with_last_selection() {
+        let workspace = Workspace::new();
+        workspace.init("first");
+        workspace.init("second");
+
+        let exec = || {
+            let mut cmd = workspace.multigit_in(workspace.path());
+            cmd.args(["exec", "--last", "--", "echo", "{name}"]);
+            cmd
+        };
+        exec().assert().failure();
+
+        LastSelection {
+            paths: vec![workspace.join("second")],
+        }
+        .save(&workspace.join(LastSelection::FILE_NAME))
+        .unwrap();
+        assert_eq!(stdout(&mut exec()), "second\n");
+    }
+
+    #[test]
+    fn run_exec_with_repository_names() {
+        let workspace = Workspace::new();
+        for name in ["api", "apps", "web-frontend"] {
+            workspace.init(name);
+        }
+        std::fs::write(
+            workspace.config(),
+            format!(
+                "[repositories.web]\npath = {:?}\nalias = \"site\"\n",
+                workspace.join("web-frontend")
+            ),
+        )
+        .unwrap();
+
+        let exec = |names: &[&str]| {
+            let mut cmd = workspace.multigit_in(workspace.path());
+            cmd.arg("exec").args(names).args(["echo", "{name}"]);
+            cmd
+        };
+        assert_eq!(
+            stdout(&mut exec(&["-r", "api", "--repo", "web"])),
+            "api\nweb-frontend\n"
+        );
+        exec(&["-r", "ap"]).assert().failure();
+        exec(&["-r", "nothing"]).assert().failure();
+
+        let output = stdout(
+            workspace
+                .multigit()
+                .args(["exec", "--repo", "site", "--", "echo", "{name}"]),
+        );
+        assert_eq!(output, "web-frontend\n");
+
+        // Words before a `--` name repositories; an option among them keeps them all passthrough.
+        let output = stdout(
+            workspace
+                .multigit_in(workspace.path())
+                .args(["exec", "api", "web", "--", "echo", "{name}", "--"]),
+        );
+        assert_eq!(output, "api --\nweb-frontend --\n");
+        let output = stdout(
+            workspace
+                .multigit_in(workspace.path())
+                .args(["exec", "--", "echo", "{name}", "--", "x"]),
+        );
+        assert_eq!(output, "api -- x\napps -- x\nweb-frontend -- x\n");
+
+        let output = stdout(
+            workspace
+                .multigit_in(workspace.path())
+                .args(["list", "web", "-r", "api"]),
+        );
+        assert!(output.contains("api"));
+        assert!(output.contains("web-frontend"));
+        assert!(!output.contains("apps"));
+    }
+
+    #[test]
+    fn run_passes_trailing_arguments_through() {
+        let workspace = Workspace::new();
+        let repo = workspace.init("example");
+        commit(&repo, "First");
+        std::fs::write(workspace.join("example/file.txt"), "contents").unwrap();
+
+        // Arguments that aren't multigit's own go to git or the command, with or without `--`.
+        workspace
+            .multigit_in(workspace.path())
+            .args(["add", "."])
+            .assert()
+            .success();
+        assert_eq!(
+            stdout(workspace.multigit_in(workspace.path()).args([
+                "exec",
+                "git",
+                "status",
+                "--porcelain"
+            ])),
+            "A  file.txt\n"
+        );
+        assert_eq!(
+            stdout(
+                workspace
+                    .multigit_in(workspace.path())
+                    .args(["exec", "--", "echo", "--repo"])
+            ),
+            "--repo\n"
+        );
+    }
+
+    #[test]
+    fn run_path() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+
+        let output = stdout(workspace.multigit_in(workspace.path()).args(["path", "ex"]));
+        assert_eq!(output, format!("{}\n", workspace.join("example").display()));
+    }
+
+    #[test]
+    fn run_doctor_and_relocate() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        let old = workspace.join("old").join("app");
+        let new = workspace.join("workspace").join("app");
+        git2::Repository::clone(workspace.join("upstream").to_str().unwrap(), &old).unwrap();
+        std::fs::create_dir_all(workspace.join("workspace")).unwrap();
+
+        let multigit = |args: &[&std::ffi::OsStr]| workspace.multigit().args(args).assert();
+        multigit(&["register".as_ref(), old.as_ref()]).success();
+        multigit(&["register".as_ref(), workspace.join("workspace").as_ref()]).success();
+        multigit(&["doctor".as_ref()]).success();
+
+        std::fs::rename(&old, &new).unwrap();
+        let output = multigit(&["doctor".as_ref()])
+            .failure()
+            .get_output()
+            .stdout
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains(&format!("It may have moved to {}", new.display())));
+
+        multigit(&["relocate".as_ref(), old.as_ref(), new.as_ref()]).success();
+        multigit(&["doctor".as_ref()]).success();
+
+        std::fs::remove_dir_all(&new).unwrap();
+        multigit(&["doctor".as_ref()]).failure();
+        multigit(&["doctor".as_ref(), "--prune".as_ref()]).success();
+        multigit(&["doctor".as_ref()]).success();
+    }
+
+    #[test]
+    fn run_relocate_keeps_key_and_path_form() {
+        let workspace = Workspace::new();
+        workspace.init("old/web");
+        std::fs::write(
+            workspace.config(),
+            format!(
+                "version = {}\n[repositories.web]\npath = \"old/web\"\nalias = \"site\"\n",
+                multigit::CONFIG_VERSION
+            ),
+        )
+        .unwrap();
+        std::fs::create_dir_all(workspace.join("new")).unwrap();
+        std::fs::rename(workspace.join("old/web"), workspace.join("new/web")).unwrap();
+
+        workspace
+            .multigit()
+            .arg("relocate")
+            .arg(workspace.join("old/web"))
+            .arg(workspace.join("new/web"))
+            .assert()
+            .success();
+        let content = std::fs::read_to_string(workspace.config()).unwrap();
+        assert!(content.contains("[repositories.web]\npath = \"new/web\"\nalias = \"site\"\n"));
+    }
+
+    #[test]
+    fn run_doctor_prunes_only_the_broken_entry_of_a_shared_key() {
+        let workspace = Workspace::new();
+        workspace.init("web");
+        std::fs::write(
+            workspace.config(),
+            format!(
+                "version = {}\n[repositories.work]\npath = {:?}\n\n[directories.work]\npath = {:?}\n",
+                multigit::CONFIG_VERSION,
+                workspace.join("web"),
+                workspace.join("missing")
+            ),
+        )
+        .unwrap();
+
+        let output = stdout(workspace.multigit().args(["doctor", "--prune"]));
+        assert!(output.contains("Removed 1 entries from the config."));
+        let content = std::fs::read_to_string(workspace.config()).unwrap();
+        assert!(content.contains("[repositories.work]"));
+        assert!(!content.contains("[directories.work]"));
+    }
+
+    #[test]
+    fn run_doctor_only_writes_config_with_prune() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        git2::Repository::clone(
+            workspace.join("upstream").to_str().unwrap(),
+            workspace.join("clone"),
+        )
+        .unwrap();
+        let original = format!(
+            "version = {}\n[repositories.clone]\npath = {:?}\n",
+            multigit::CONFIG_VERSION,
+            workspace.join("clone")
+        );
+        std::fs::write(workspace.config(), &original).unwrap();
+
+        workspace.multigit().arg("doctor").assert().success();
+        assert_eq!(
+            std::fs::read_to_string(workspace.config()).unwrap(),
+            original
+        );
+
+        workspace
+            .multigit()
+            .args(["doctor", "--prune"])
+            .assert()
+            .success();
+        assert!(std::fs::read_to_string(workspace.config())
+            .unwrap()
+            .contains("remote_url = "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_exec_deduplicates_repositories() {
+        let workspace = Workspace::new();
+        let repository = workspace.join("projects").join("example");
+        let repo = git2::Repository::init(&repository).unwrap();
+        commit(&repo, "First");
+        repo.worktree("feature", &workspace.join("projects").join("feature"), None)
+            .unwrap();
+        std::os::unix::fs::symlink(&repository, workspace.join("link")).unwrap();
+
+        for path in [
+            repository,
+            workspace.join("link"),
+            workspace.join("projects"),
+        ] {
+            workspace
+                .multigit()
+                .arg("register")
+                .arg(path)
+                .assert()
+                .success();
+        }
+
+        let output = stdout(workspace.multigit().args(["exec", "--", "echo", "{name}"]));
+        assert_eq!(output.lines().count(), 1);
+    }
+
+    #[test]
+    fn run_with_portable_config_paths() {
+        let workspace = Workspace::new();
+        workspace.init(Path::new("repositories").join("first"));
+        workspace.init(Path::new("elsewhere").join("second"));
+        std::fs::create_dir_all(workspace.join("dotfiles")).unwrap();
+        let config = workspace.join("dotfiles").join("config.toml");
+        std::fs::write(
+            &config,
+            "path_style = \"relative\"\n\
+             [directories.repositories]\n\
+             path = \"../repositories\"\n",
+        )
+        .unwrap();
+
+        let mut cmd = Command::cargo_bin("multigit").unwrap();
+        cmd.arg("--config").arg(&config);
+        cmd.arg("register")
+            .arg(workspace.join("elsewhere").join("second"));
+        cmd.assert().success();
+        let content = std::fs::read_to_string(&config).unwrap();
+        assert!(content.contains("path = \"../repositories\""));
+        assert!(content.contains("path = \"../elsewhere/second\""));
+
+        std::fs::write(
+            &config,
+            content.replace("../elsewhere", "$MULTIGIT_TEST_ROOT/elsewhere"),
+        )
+        .unwrap();
+        let mut cmd = Command::cargo_bin("multigit").unwrap();
+        cmd.arg("--config").arg(&config);
+        cmd.env("MULTIGIT_TEST_ROOT", workspace.path());
+        cmd.args(["exec", "--", "echo", "{name}"]);
+        assert_eq!(stdout(&mut cmd), "second\nfirst\n");
+    }
+
+    #[test]
+    fn run_exec_with_metadata() {
+        let workspace = Workspace::new();
+        for name in ["first", "second"] {
+            workspace.init(name);
+        }
+
+        workspace
+            .multigit()
+            .arg("register")
+            .arg(workspace.path())
+            .assert()
+            .success();
+        workspace
+            .multigit()
+            .args(["repo", "set", "second", "owner=alice", "team=tools"])
+            .assert()
+            .success();
+
+        let output = stdout(
+            workspace
+                .multigit()
+                .args(["exec", "--filter", "team=tools", "--", "echo"])
+                .args(["{name}", "{owner}", "{meta.team}"]),
+        );
+        assert_eq!(output, "second alice tools\n");
+
+        // Values that look like placeholders are used as they are.
+        workspace
+            .multigit()
+            .args(["repo", "set", "first", "note={meta.note}", "other={owner}"])
+            .assert()
+            .success();
+        let output = stdout(workspace.multigit().args([
+            "exec",
+            "--repo",
+            "first",
+            "--",
+            "echo",
+            "{meta.note}",
+            "{meta.other}",
+        ]));
+        assert_eq!(output, "{meta.note} {owner}\n");
+    }
+
+    #[test]
+    fn run_with_project_config() {
+        let workspace = Workspace::new();
+        for name in ["first", "second"] {
+            workspace.init(name);
+        }
+        std::fs::write(
+            workspace.join(multigit::PROJECT_CONFIG),
+            "exclude = [\"second\"]\n\n[directories.workspace]\npath = \".\"\n",
+        )
+        .unwrap();
+
+        let mut cmd = workspace.multigit();
+        cmd.current_dir(workspace.path());
+        cmd.args(["exec", "--", "echo", "{name}"]);
+        assert_eq!(stdout(&mut cmd), "first\n");
+    }
+
+    #[test]
+    fn run_with_profiles() {
+        let workspace = Workspace::new();
+        for name in ["personal/first", "work/second", "work/vendored"] {
+            workspace.init(name);
+        }
+        // Exclusions in the default profile apply to every profile.
+        std::fs::write(
+            workspace.config(),
+            format!(
+                "exclude = [{:?}]\n\n[directories.personal]\npath = \"personal\"\n\n[profiles.work.directories.work]\npath = \"work\"\n",
+                workspace.join("work/vendored")
+            ),
+        )
+        .unwrap();
+
+        let exec = |profile: Option<&str>| {
+            let mut cmd = workspace.multigit();
+            if let Some(profile) = profile {
+                cmd.env("MULTIGIT_PROFILE", profile);
+            }
+            cmd.args(["exec", "--", "echo", "{name}"]);
+            stdout(&mut cmd)
+        };
+        assert_eq!(exec(None), "first\n");
+        assert_eq!(exec(Some("work")), "second\n");
+
+        // Each profile keeps its own last run.
+        let last_run = |name: &str| {
+            LastRun::load(&workspace.join(name))
+                .unwrap()
+                .unwrap()
+                .results
+                .into_iter()
+                .map(|result| result.path)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            last_run("last-run.toml"),
+            [workspace.join("personal/first")]
+        );
+        assert_eq!(
+            last_run("last-run.work.toml"),
+            [workspace.join("work/second")]
+        );
+
+        workspace
+            .multigit()
+            .args(["profile", "use", "work"])
+            .assert()
+            .success();
+        assert_eq!(exec(None), "second\n");
+        assert_eq!(exec(Some("default")), "first\n");
+    }
+
+    #[test]
+    fn run_with_deleted_default_profile() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+        std::fs::write(
+            workspace.config(),
+            format!(
+                "profile = \"gone\"\n\n[repositories.example]\npath = {:?}\n",
+                workspace.join("example")
+            ),
+        )
+        .unwrap();
+
+        // A configured profile that was removed falls back to the default profile, with a warning.
+        let output = stdout(workspace.multigit().args(["exec", "echo", "{name}"]));
+        assert!(output.contains("The configured profile `gone` doesn't exist"));
+        assert!(output.ends_with("\nexample\n"));
+        workspace
+            .multigit()
+            .args(["profile", "use", "default"])
+            .assert()
+            .success();
+        // Naming a missing profile is still an error.
+        workspace
+            .multigit()
+            .args(["--profile", "gone", "profile", "list"])
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn run_config_with_key() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+        std::fs::write(
+            workspace.config(),
+            "[daemon]\ninterval = \"5m\"\n\n[repositories.\"my.repo\"]\npath = \"example\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            stdout(workspace.multigit().args(["config", "daemon.interval"])),
+            "5m\n"
+        );
+        assert_eq!(
+            stdout(
+                workspace
+                    .multigit()
+                    .args(["config", "repositories.\"my.repo\".path"])
+            ),
+            "example\n"
+        );
+        let output = workspace
+            .multigit()
+            .args(["config", "daemon.missing"])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("No config value at `daemon.missing`"));
+    }
+
+    #[test]
+    fn run_completions() {
+        let workspace = Workspace::new();
+        assert!(stdout(
+            workspace
+                .multigit()
+                .args(["completions", "--shell", "bash"])
+        )
+        .contains("multigit"));
+
+        let output = workspace
+            .multigit()
+            .args(["completions", "--shell", "tcsh"])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Unsupported shell `tcsh`, expected one of: bash"));
+    }
+
+    #[test]
+    fn run_config_migration() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+        let original =
+            "# Work repositories\n[repositories.example]\npath = \"example\"\ncolour = \"blue\"\n";
+        std::fs::write(workspace.config(), original).unwrap();
+
+        workspace
+            .multigit()
+            .args(["repo", "set", "example", "owner=alice"])
+            .assert()
+            .success();
+
+        let backup = std::fs::read_to_string(workspace.join("config.toml.v0.bak")).unwrap();
+        assert_eq!(backup, original);
+        let content = std::fs::read_to_string(workspace.config()).unwrap();
+        assert!(content.starts_with(&format!("version = {}\n", multigit::CONFIG_VERSION)));
+        assert!(content.contains("# Work repositories\n"));
+        assert!(content.contains("colour = \"blue\"\nowner = \"alice\"\n"));
+    }
+
+    #[test]
+    fn run_aliases() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+        std::fs::write(
+            workspace.config(),
+            "[aliases]\nnames = \"exec -- echo {name}\"\nshout = \"!echo {name} | tr a-z A-Z\"\n",
+        )
+        .unwrap();
+
+        for (alias, expected) in [("names", "example\n"), ("shout", "EXAMPLE\n")] {
+            assert_eq!(
+                stdout(workspace.multigit_in(workspace.path()).arg(alias)),
+                expected
+            );
+        }
+        workspace.multigit().arg("unknown").assert().failure();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_plugin() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let workspace = Workspace::new();
+        workspace.init("example");
+        let bin = workspace.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let plugin = bin.join("multigit-hello");
+        std::fs::write(
+            &plugin,
+            "#!/bin/sh\necho \"$@\"\necho \"$MULTIGIT_REPOSITORIES\"\ntest -f \"$MULTIGIT_REPOSITORIES_JSON\"\nexit ${EXIT_CODE:-0}\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = std::env::join_paths(
+            std::iter::once(bin).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+        )
+        .unwrap();
+
+        let mut cmd = workspace.multigit_in(workspace.path());
+        cmd.env("PATH", &path);
+        cmd.args(["hello", "--", "--greeting", "hi"]);
+        assert_eq!(
+            stdout(&mut cmd),
+            format!("--greeting hi\n{}\n", workspace.join("example").display())
+        );
+
+        // The plugin's exit code is passed on, without an error of multigit's own.
+        workspace
+            .multigit_in(workspace.path())
+            .env("PATH", &path)
+            .env("EXIT_CODE", "7")
+            .arg("hello")
+            .assert()
+            .code(7)
+            .stderr("");
+
+        // Selection options are taken after the plugin name too, rather than passed to it.
+        workspace.init("other");
+        LastSelection {
+            paths: vec![workspace.join("example")],
+        }
+        .save(&workspace.join(LastSelection::FILE_NAME))
+        .unwrap();
+        let mut cmd = workspace.multigit_in(workspace.path());
+        cmd.env("PATH", &path).args(["hello", "--last", "hi"]);
+        assert_eq!(
+            stdout(&mut cmd),
+            format!("hi\n{}\n", workspace.join("example").display())
+        );
+        workspace
+            .multigit_in(workspace.path())
+            .env("PATH", &path)
+            .args(["hello", "--profile", "missing"])
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn run_native_fetch() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        git2::Repository::clone(
+            workspace.join("upstream").to_str().unwrap(),
+            workspace.join("workspace").join("clone"),
+        )
+        .unwrap();
+        let head = commit(&upstream, "Second");
+        let unreachable = workspace.init("workspace/unreachable");
+        unreachable
+            .remote("origin", workspace.join("missing").to_str().unwrap())
+            .unwrap();
+
+        // The summary counts only the repositories that were fetched.
+        let output = workspace
+            .multigit_in("workspace")
+            .args(["fetch", "--native"])
+            .assert()
+            .failure()
+            .get_output()
+            .stdout
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Fetched 1 repositories"));
+
+        let clone = git2::Repository::open(workspace.join("workspace").join("clone")).unwrap();
+        let branch = upstream.head().unwrap().shorthand().unwrap().to_string();
+        let fetched = clone
+            .find_reference(&format!("refs/remotes/origin/{}", branch))
+            .unwrap();
+        assert_eq!(fetched.target(), Some(head));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_push_with_timeouts_and_retries() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        let clone = git2::Repository::clone(
+            workspace.join("upstream").to_str().unwrap(),
+            workspace.join("workspace").join("clone"),
+        )
+        .unwrap();
+        commit(&clone, "Second");
+        let push = |ssh_command: &str, options: &[&str]| {
+            let output = workspace
+                .multigit_in("workspace")
+                .env("GIT_SSH_COMMAND", ssh_command)
+                .arg("push")
+                .args(options)
+                .assert()
+                .failure()
+                .get_output()
+                .clone();
+            // Retries are logged, which goes to stdout, and git's errors to stderr.
+            String::from_utf8([output.stdout, output.stderr].concat()).unwrap()
+        };
+
+        // The upstream refuses pushes to its checked out branch, which a retry won't change.
+        assert!(!push("", &["--retries", "2"]).contains("retrying"));
+
+        clone
+            .remote_set_url("origin", "ssh://example.invalid/upstream.git")
+            .unwrap();
+        let unreachable = "echo 'ssh: Could not resolve hostname' >&2; exit 255; :";
+        let output = push(unreachable, &["--retries", "1"]);
+        assert_eq!(output.matches("retrying in 1s").count(), 1);
+
+        let start = std::time::Instant::now();
+        let hanging = "sleep 10 </dev/null >/dev/null 2>&1; :";
+        assert!(push(hanging, &["--timeout", "1s"]).contains("Timed out after 1s"));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn run_native_fetch_times_out_on_silent_remote() {
+        let workspace = Workspace::new();
+        let clone = workspace.init("workspace/clone");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("git://{}/upstream.git", listener.local_addr().unwrap());
+        clone.remote("origin", &url).unwrap();
+        // Accept the connection and never answer.
+        let _server = std::thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+
+        let start = std::time::Instant::now();
+        let output = workspace
+            .multigit_in("workspace")
+            .args(["fetch", "--native", "--timeout", "1s"])
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Timed out after 1s"));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn run_daemon_once() {
+        let workspace = Workspace::new();
+        let upstream = workspace.init("upstream");
+        commit(&upstream, "First");
+        git2::Repository::clone(
+            workspace.join("upstream").to_str().unwrap(),
+            workspace.join("workspace").join("clone"),
+        )
+        .unwrap();
+
+        workspace
+            .multigit_in("workspace")
+            .args(["daemon", "--once"])
+            .assert()
+            .success();
+
+        let output = stdout(
+            workspace
+                .multigit_in("workspace")
+                .args(["list", "--detailed"]),
+        );
+        assert!(output.contains("fetched"));
+    }
+
+    #[test]
+    fn run_daemon_refuses_to_start_twice() {
+        use fs2::FileExt;
+
+        let workspace = Workspace::new();
+        std::fs::write(workspace.config(), "[profiles.work]\n").unwrap();
+        std::fs::write(workspace.join("daemon.log"), "earlier output\n").unwrap();
+        let lock = std::fs::File::create(workspace.join(multigit::DAEMON_LOCK_FILE_NAME)).unwrap();
+        lock.try_lock_exclusive().unwrap();
+
+        let output = workspace
+            .multigit()
+            .arg("daemon")
+            .assert()
+            .failure()
+            .get_output()
+            .stderr
+            .clone();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Another multigit daemon is already running"));
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("daemon.log")).unwrap(),
+            "earlier output\n"
+        );
+
+        // Another profile has its own daemon.
+        workspace
+            .multigit()
+            .args(["--profile", "work", "daemon", "--once"])
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn run_exec_exit_codes() {
+        let workspace = Workspace::new();
+        workspace.init("example");
+
+        workspace
+            .multigit_in(workspace.path())
+            .args(["exec", "--", "false"])
+            .assert()
+            .code(multigit::RunError::TOTAL_FAILURE);
+        workspace
+            .multigit_in(workspace.path())
+            .args(["commit", "-m", "Nothing to commit"])
+            .assert()
+            .code(multigit::RunError::NOTHING_TO_DO);
+        workspace
+            .multigit_in(workspace.path())
+            .args(["status", "--filter", "dirty"])
+            .assert()
+            .success();
+    }
+
+    #[test]
+    fn run_fail_fast() {
+        let workspace = Workspace::new();
+        for name in ["first", "second"] {
+            workspace.init(name);
+        }
+        std::fs::write(workspace.config(), "fail_fast = true\n").unwrap();
+
+        let exec = |options: &[&str]| {
+            let output = workspace
+                .multigit_in(workspace.path())
+                .args(options)
+                .args(["--shell", "--", "echo {name}; false"])
+                .assert()
+                .code(multigit::RunError::TOTAL_FAILURE)
+                .get_output()
+                .stdout
+                .clone();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(exec(&["exec"]), "first\n");
+        assert_eq!(exec(&["--keep-going", "exec"]), "first\nsecond\n");
+        assert_eq!(exec(&["exec", "--keep-going"]), "first\nsecond\n");
+
+        workspace
+            .multigit_in(workspace.path())
+            .args(["--fail-fast", "exec", "--keep-going", "--", "true"])
+            .assert()
+            .failure();
+    }
 }
@@ -0,0 +1,108 @@
+//! External commands: an unknown subcommand `name` runs a `multigit-<name>` executable from
+//! `PATH`, in the way git and cargo run theirs.
+//!
+//! The plugin is given the repositories multigit selected, so it can reuse the usual filters,
+//! names, `--pick` and profiles:
+//!
+//! - `MULTIGIT_REPOSITORIES`: the repository paths, one per line.
+//! - `MULTIGIT_REPOSITORIES_JSON`: the path of a JSON file with an array of the repositories'
+//!   config entries, each with its `name` added.
+//! - `MULTIGIT`: the multigit executable, and `MULTIGIT_PROFILE`: the profile in use, for calling
+//!   back into multigit.
+
+use crate::{Filter, Multigit, RepositoryEntry};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// The prefix of plugin executables.
+pub const PLUGIN_PREFIX: &str = "multigit-";
+
+/// A repository as described to a plugin.
+#[derive(Serialize)]
+struct PluginRepository<'a> {
+    name: String,
+    #[serde(flatten)]
+    entry: &'a RepositoryEntry,
+}
+
+/// Returns the path of the `multigit-<name>` executable on `PATH`, if there is one.
+pub fn find_plugin(name: &str) -> Option<PathBuf> {
+    let file_name = format!("{}{}{}", PLUGIN_PREFIX, name, env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|directory| directory.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+impl Multigit {
+    /// Runs the `multigit-<name>` plugin with `arguments`, passing it the selected repositories.
+    ///
+    /// The repositories are selected like those of a built-in command called `name`, so its
+    /// default filters can be configured too. Returns the plugin's exit status.
+    pub fn plugin(
+        &self,
+        name: &str,
+        filter: Option<&Vec<Filter>>,
+        all: &bool,
+        arguments: &[String],
+    ) -> Result<ExitStatus> {
+        let program = find_plugin(name).ok_or_else(|| {
+            anyhow!(
+                "Unknown command `{}`, and no `{}{}` was found on PATH",
+                name,
+                PLUGIN_PREFIX,
+                name
+            )
+        })?;
+        let repositories = self.command_repositories(name, filter, all)?;
+
+        let paths: Vec<String> = repositories
+            .iter()
+            .map(|repository| repository.path.display().to_string())
+            .collect();
+        let described: Vec<PluginRepository> = repositories
+            .iter()
+            .map(|repository| PluginRepository {
+                name: repository.name(),
+                entry: repository,
+            })
+            .collect();
+        // A fresh, private file, so other users can't swap it for a symlink. It's removed when
+        // dropped.
+        let mut json_file = tempfile::Builder::new()
+            .prefix(&format!("{}{}-", PLUGIN_PREFIX, name))
+            .suffix(".json")
+            .tempfile()
+            .context("Failed to create the repository list for the plugin")?;
+        serde_json::to_writer_pretty(&mut json_file, &described)?;
+        json_file
+            .flush()
+            .context("Failed to write the repository list for the plugin")?;
+
+        log::debug!("Running plugin {} {:?}", program.display(), arguments);
+        Command::new(&program)
+            .args(arguments)
+            .env("MULTIGIT", env::current_exe()?)
+            .env("MULTIGIT_PROFILE", self.config.profile_name())
+            .env("MULTIGIT_REPOSITORIES", paths.join("\n"))
+            .env("MULTIGIT_REPOSITORIES_JSON", json_file.path())
+            .status()
+            .with_context(|| format!("Failed to run {}", program.display()))
+    }
+}
@@ -0,0 +1,76 @@
+//! Shell integration scripts printed by `multigit shell-init`.
+//!
+//! Each script defines an `mg` function: `mg cd <name>` changes to a repository's directory and
+//! any other arguments are passed on to multigit. Repository names are completed by calling
+//! `multigit path --list` when completion is requested, so they are always current.
+
+/// The shells `multigit shell-init` can generate a script for.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+const BASH: &str = r#"mg() {
+    if [ "$1" = "cd" ]; then
+        shift
+        local dir
+        dir="$(command {program} path "$@")" && cd "$dir"
+    else
+        command {program} "$@"
+    fi
+}
+
+_mg() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "cd" -- "$cur"))
+    elif [ "$COMP_CWORD" -eq 2 ] && [ "${COMP_WORDS[1]}" = "cd" ]; then
+        COMPREPLY=($(compgen -W "$(command {program} path --list 2>/dev/null)" -- "$cur"))
+    fi
+}
+complete -F _mg mg
+"#;
+
+const ZSH: &str = r#"mg() {
+    if [[ "$1" == "cd" ]]; then
+        shift
+        local dir
+        dir="$(command {program} path "$@")" && cd "$dir"
+    else
+        command {program} "$@"
+    fi
+}
+
+_mg() {
+    if (( CURRENT == 2 )); then
+        compadd cd
+    elif (( CURRENT == 3 )) && [[ "${words[2]}" == "cd" ]]; then
+        compadd -- ${(f)"$(command {program} path --list 2>/dev/null)"}
+    fi
+}
+(( $+functions[compdef] )) && compdef _mg mg
+"#;
+
+const FISH: &str = r#"function mg
+    if test "$argv[1]" = cd
+        set -l dir (command {program} path $argv[2..-1]); and cd $dir
+    else
+        command {program} $argv
+    end
+end
+
+complete -c mg -f -n __fish_use_subcommand -a cd
+complete -c mg -f -n '__fish_seen_subcommand_from cd' -a '(command {program} path --list 2>/dev/null)'
+"#;
+
+/// Returns the shell integration script for `shell`, calling multigit as `program`.
+pub fn shell_init(shell: InitShell, program: &str) -> String {
+    let template = match shell {
+        InitShell::Bash => BASH,
+        InitShell::Zsh => ZSH,
+        InitShell::Fish => FISH,
+    };
+    template.replace("{program}", program)
+}
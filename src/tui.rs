@@ -0,0 +1,322 @@
+//! An interactive terminal dashboard for the registered repositories.
+//!
+//! Shows every repository in a table that refreshes periodically, and runs common operations on
+//! the selected repository with single key presses.
+
+use crate::{open_in_git_ui, EntryState, Filter, Multigit, RepositoryEntry, RepositorySummary};
+use anyhow::{anyhow, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::{self, IsTerminal};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the table is refreshed while idle.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the event loop checks for the results of a background refresh.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The key bindings shown in the footer.
+const HELP: &str =
+    "↑/↓ select  f fetch  p pull  P push  d diff  g git ui  s shell  r refresh  q quit";
+
+/// The dashboard's state.
+struct App {
+    rows: Vec<RepositorySummary>,
+    table_state: TableState,
+    message: String,
+    last_refresh: Instant,
+    /// Asks the background thread to probe the repositories again.
+    probe: Sender<()>,
+    /// The results of the background probes.
+    probed: Receiver<Result<Vec<RepositorySummary>>>,
+    /// Whether a probe has been requested and its results haven't arrived yet.
+    refreshing: bool,
+    /// Whether the pending probe was requested with `r`, so its completion is reported.
+    manual_refresh: bool,
+}
+
+impl App {
+    /// Probes the repositories once, and starts a thread that probes them again on request.
+    fn new(multigit: &Multigit, filter: Option<&Vec<Filter>>) -> Result<Self> {
+        let rows = multigit.summaries(filter)?;
+
+        // The prober selects the same repositories, including any that were picked, without
+        // prompting again.
+        let mut prober = Multigit::new(multigit.config.clone(), multigit.directory.clone())?;
+        prober.state_directory = multigit.state_directory.clone();
+        prober.only = match multigit.picked.get() {
+            Some(picked) => Some(picked.clone()),
+            None => multigit.only.clone(),
+        };
+        let filter = filter.cloned();
+        let (probe, requests) = mpsc::channel::<()>();
+        let (results, probed) = mpsc::channel();
+        thread::spawn(move || {
+            // Stops once the dashboard drops its end of either channel.
+            while requests.recv().is_ok() {
+                while requests.try_recv().is_ok() {}
+                if results.send(prober.summaries(filter.as_ref())).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut app = App {
+            rows: Vec::new(),
+            table_state: TableState::default(),
+            message: String::new(),
+            last_refresh: Instant::now(),
+            probe,
+            probed,
+            refreshing: false,
+            manual_refresh: false,
+        };
+        app.show(rows);
+        Ok(app)
+    }
+
+    /// Asks the background thread to probe every repository again, unless a probe is pending.
+    fn refresh(&mut self) {
+        if !self.refreshing {
+            self.refreshing = self.probe.send(()).is_ok();
+        }
+        self.last_refresh = Instant::now();
+    }
+
+    /// Shows the results of a finished background probe, if there are any.
+    fn receive(&mut self) {
+        match self.probed.try_recv() {
+            Ok(Ok(rows)) => {
+                self.show(rows);
+                if self.manual_refresh {
+                    self.message = "Refreshed".to_string();
+                }
+            }
+            // Keep the previous rows, so a transient error doesn't end the dashboard.
+            Ok(Err(e)) => self.message = format!("Refresh failed: {:#}", e),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.message = "Refreshing stopped unexpectedly".to_string();
+            }
+        }
+        self.refreshing = false;
+        self.manual_refresh = false;
+        self.last_refresh = Instant::now();
+    }
+
+    /// Replaces the rows, keeping the selection where possible.
+    fn show(&mut self, rows: Vec<RepositorySummary>) {
+        let selected_path = self.selected().map(|row| row.entry.path.clone());
+        self.rows = rows;
+        let selected = selected_path
+            .and_then(|path| self.rows.iter().position(|row| row.entry.path == path))
+            .or(if self.rows.is_empty() { None } else { Some(0) });
+        self.table_state.select(selected);
+    }
+
+    fn selected(&self) -> Option<&RepositorySummary> {
+        self.table_state
+            .selected()
+            .and_then(|index| self.rows.get(index))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, message_area, help_area] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let header = Row::new(["Name", "Branch", "State", "Ahead", "Behind", "Stashes"]).bold();
+        let rows = self.rows.iter().map(|row| {
+            let state = match &row.state {
+                Some(state) if state.entries.contains(&EntryState::Dirty) => {
+                    Cell::from("Dirty").fg(Color::Yellow)
+                }
+                Some(_) => Cell::from("Clean"),
+                None => Cell::from("Error").fg(Color::Red),
+            };
+            Row::new([
+                Cell::from(row.name.clone()).fg(Color::Cyan),
+                Cell::from(row.branch.clone().unwrap_or_default()),
+                state,
+                Cell::from(display_flag(row.ahead)),
+                Cell::from(display_flag(row.behind)),
+                Cell::from(if row.has_stashes { "yes" } else { "" }),
+            ])
+        });
+        let widths = [
+            Constraint::Fill(2),
+            Constraint::Fill(2),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(8),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .row_highlight_style(Style::new().reversed())
+            .block(Block::bordered().title(" multigit "));
+        frame.render_stateful_widget(table, table_area, &mut self.table_state);
+
+        frame.render_widget(Paragraph::new(self.message.as_str()), message_area);
+        frame.render_widget(Paragraph::new(HELP).dark_gray(), help_area);
+    }
+
+    /// Runs an action on the selected repository, with the terminal restored for its output.
+    fn run_on_selected<F>(&mut self, terminal: &mut DefaultTerminal, action: F) -> Result<()>
+    where
+        F: FnOnce(&RepositoryEntry) -> Result<String>,
+    {
+        let Some(row) = self.selected() else {
+            return Ok(());
+        };
+        let repository = row.entry.clone();
+
+        suspend()?;
+        let result = action(&repository);
+        resume(terminal)?;
+
+        self.message = match result {
+            Ok(message) => message,
+            Err(e) => format!("{}: {}", repository.name(), e),
+        };
+        self.refresh();
+        Ok(())
+    }
+}
+
+/// Displays an ahead/behind flag, leaving it blank when there is no upstream.
+fn display_flag(flag: Option<bool>) -> &'static str {
+    match flag {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "",
+    }
+}
+
+/// Leaves the alternate screen so a command can use the terminal normally.
+fn suspend() -> Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
+
+/// Returns to the dashboard after `suspend`.
+fn resume(terminal: &mut DefaultTerminal) -> Result<()> {
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    enable_raw_mode()?;
+    terminal.clear()?;
+    Ok(())
+}
+
+/// Runs a command in a repository and waits for Enter, so its output can be read.
+fn run_and_wait(repository: &RepositoryEntry, program: &str, args: &[&str]) -> Result<String> {
+    println!(
+        "Running `{} {}` in {}\n",
+        program,
+        args.join(" "),
+        repository.path.display()
+    );
+    let status = Command::new(program)
+        .args(args)
+        .current_dir(&repository.path)
+        .status()?;
+    println!("\nPress Enter to return to multigit.");
+    io::stdin().read_line(&mut String::new())?;
+    if !status.success() {
+        return Err(anyhow!(
+            "`{} {}` failed with exit code {:?}",
+            program,
+            args.join(" "),
+            status.code()
+        ));
+    }
+    Ok(format!(
+        "{}: `{} {}` succeeded",
+        repository.name(),
+        program,
+        args.join(" ")
+    ))
+}
+
+impl Multigit {
+    /// Shows an interactive dashboard of the selected repositories.
+    pub fn tui(&self, filter: Option<&Vec<Filter>>) -> Result<()> {
+        if !io::stdout().is_terminal() {
+            return Err(anyhow!("The dashboard requires an interactive terminal"));
+        }
+        // Probe the repositories before taking over the screen, so `--pick` can prompt.
+        let mut app = App::new(self, filter)?;
+        let mut terminal = ratatui::init();
+        let result = Self::run_tui(&mut terminal, &mut app);
+        ratatui::restore();
+        result
+    }
+
+    fn run_tui(terminal: &mut DefaultTerminal, app: &mut App) -> Result<()> {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+
+            app.receive();
+            if !app.refreshing && app.last_refresh.elapsed() >= REFRESH_INTERVAL {
+                app.refresh();
+            }
+            if !event::poll(POLL_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => app.table_state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => app.table_state.select_previous(),
+                KeyCode::Char('r') => {
+                    app.refresh();
+                    app.manual_refresh = true;
+                    app.message = "Refreshing…".to_string();
+                }
+                KeyCode::Char('f') => app.run_on_selected(terminal, |repository| {
+                    run_and_wait(repository, "git", &["fetch"])
+                })?,
+                KeyCode::Char('p') => app.run_on_selected(terminal, |repository| {
+                    run_and_wait(repository, "git", &["pull"])
+                })?,
+                KeyCode::Char('P') => app.run_on_selected(terminal, |repository| {
+                    run_and_wait(repository, "git", &["push"])
+                })?,
+                KeyCode::Char('d') => app.run_on_selected(terminal, |repository| {
+                    run_and_wait(repository, "git", &["diff"])
+                })?,
+                KeyCode::Char('g') => app.run_on_selected(terminal, |repository| {
+                    open_in_git_ui(&repository.path)?;
+                    Ok(format!("{}: opened git ui", repository.name()))
+                })?,
+                KeyCode::Char('s') => app.run_on_selected(terminal, |repository| {
+                    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+                    Command::new(&shell)
+                        .current_dir(&repository.path)
+                        .status()?;
+                    Ok(format!("{}: shell exited", repository.name()))
+                })?,
+                _ => {}
+            }
+        }
+    }
+}
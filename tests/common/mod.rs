@@ -0,0 +1,29 @@
+//! Helpers shared by the library tests and the command line tests in `src/main.rs`.
+
+/// Creates a temporary directory that is removed when it is dropped, even if the test fails.
+pub fn tempdir() -> tempfile::TempDir {
+    // Repositories in hidden directories are skipped, so don't use the default `.tmp` prefix.
+    tempfile::Builder::new()
+        .prefix("multigit-")
+        .tempdir()
+        .unwrap()
+}
+
+/// Commits the current index of a repository on top of HEAD.
+pub fn commit(repo: &git2::Repository, message: &str) -> git2::Oid {
+    let signature = git2::Signature::now("Multigit", "multigit@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.index().unwrap().write_tree().unwrap())
+        .unwrap();
+    let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+    let parents = parent.iter().collect::<Vec<_>>();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
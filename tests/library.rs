@@ -0,0 +1,97 @@
+//! Tests of the library API, used without the command line tool.
+
+mod common;
+
+use common::commit;
+use multigit::{Config, EntryState, InputArg, Multigit};
+
+#[test]
+fn summaries_and_run_reports() {
+    let directory = common::tempdir();
+    let committed = git2::Repository::init(directory.path().join("committed")).unwrap();
+    commit(&committed, "First");
+    git2::Repository::init(directory.path().join("unborn")).unwrap();
+    std::fs::write(directory.path().join("unborn").join("file.txt"), "changed").unwrap();
+
+    let multigit = Multigit::new(Config::default(), Some(directory.path().to_path_buf())).unwrap();
+
+    let mut summaries = multigit.summaries(None).unwrap();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: Vec<&str> = summaries.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["committed", "unborn"]);
+    assert!(summaries[0].branch.is_some());
+    assert_eq!(summaries[1].branch, summaries[0].branch);
+    assert!(summaries[1]
+        .state
+        .as_ref()
+        .unwrap()
+        .entries
+        .contains(&EntryState::Dirty));
+
+    let repositories = multigit.all_repositories(None).unwrap();
+    let report = multigit.run_repositories(&repositories, |repository| {
+        git2::Repository::open(&repository.path)?.head()?;
+        Ok(())
+    });
+    assert_eq!(report.total, 2);
+    assert_eq!(report.results.len(), 2);
+    assert_eq!(report.errors.len(), 1);
+    assert!(!report.is_success());
+    assert!(report.into_result().is_err());
+}
+
+#[test]
+fn summaries_survive_a_broken_repository() {
+    let directory = common::tempdir();
+    let healthy = git2::Repository::init(directory.path().join("healthy")).unwrap();
+    commit(&healthy, "First");
+    let broken = git2::Repository::init(directory.path().join("broken")).unwrap();
+    commit(&broken, "First");
+    std::fs::write(broken.path().join("index"), "not an index").unwrap();
+
+    let multigit = Multigit::new(Config::default(), Some(directory.path().to_path_buf())).unwrap();
+
+    let mut summaries = multigit.summaries(None).unwrap();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: Vec<&str> = summaries.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["broken", "healthy"]);
+    assert!(summaries[0].state.is_none());
+    assert!(summaries[1].state.is_some());
+}
+
+#[test]
+fn common_branches() {
+    let directory = common::tempdir();
+    for (name, branches) in [
+        ("api", ["feature", "api-only"]),
+        ("web", ["feature", "web-only"]),
+    ] {
+        let repo = git2::Repository::init(directory.path().join(name)).unwrap();
+        let head = repo.find_commit(commit(&repo, "First")).unwrap();
+        for branch in branches {
+            repo.branch(branch, &head, false).unwrap();
+        }
+    }
+
+    let multigit = Multigit::new(Config::default(), Some(directory.path().to_path_buf())).unwrap();
+
+    let branches = multigit.common_branches().unwrap();
+    assert!(branches.contains(&"feature".to_string()));
+    assert!(!branches.contains(&"api-only".to_string()));
+    assert!(!branches.contains(&"web-only".to_string()));
+}
+
+#[test]
+fn read_only_load_leaves_an_old_config_alone() {
+    let directory = common::tempdir();
+    let path = directory.path().join("config.toml");
+    std::fs::write(&path, "[aliases]\nsync = \"pull\"\n").unwrap();
+
+    let config = Config::load_read_only(InputArg::Path(path.clone())).unwrap();
+    assert_eq!(config.alias("sync"), Some(&"pull".to_string()));
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "[aliases]\nsync = \"pull\"\n"
+    );
+    assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+}